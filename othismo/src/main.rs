@@ -4,8 +4,11 @@
 use bson::bson;
 use std::time::Duration;
 
+use crate::othismo::acl::Permission;
 use crate::othismo::image::{Image, Object};
-use crate::othismo::Message;
+use crate::othismo::mailbox::{MailboxLimit, Overflow};
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::supervision::{RestartPolicy, Supervision};
 use bson::doc;
 use clap::{Parser, Subcommand};
 use othismo::executors::{ConsoleExecutor, EchoExecutor};
@@ -31,6 +34,10 @@ enum SubCommands {
     ImportModule {
         #[arg()]
         module_name: String,
+        #[arg()]
+        destination: Option<String>,
+        #[arg(long)]
+        replace: bool,
     },
     RemoveModule {
         #[arg()]
@@ -50,7 +57,155 @@ enum SubCommands {
         #[arg()]
         instance_name: String,
     },
+    Serve {
+        #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+        checkpoint_every: u64,
+    },
     ListObjects {},
+    Inspect {
+        #[arg()]
+        name: String,
+    },
+    History {
+        #[arg()]
+        name: String,
+    },
+    Revert {
+        #[arg()]
+        name: String,
+        #[arg()]
+        revision: i64,
+    },
+    SetHistoryRetention {
+        #[arg()]
+        revisions: u32,
+    },
+    Grant {
+        #[arg()]
+        path: String,
+        #[arg()]
+        principal: String,
+        #[arg()]
+        permission: String,
+    },
+    Revoke {
+        #[arg()]
+        path: String,
+        #[arg()]
+        principal: String,
+        #[arg()]
+        permission: String,
+    },
+    ListGrants {},
+    MintCapability {
+        #[arg()]
+        target: String,
+        #[arg()]
+        holder: String,
+    },
+    RevokeCapability {
+        #[arg()]
+        token: String,
+    },
+    ListCapabilities {},
+    Sandbox {
+        #[arg()]
+        instance_name: String,
+    },
+    Unsandbox {
+        #[arg()]
+        instance_name: String,
+    },
+    Supervise {
+        #[arg()]
+        instance_name: String,
+        #[arg()]
+        policy: String,
+        #[arg(long, default_value_t = 3)]
+        max_restarts: u32,
+        #[arg(long, default_value_t = 5)]
+        within_seconds: u64,
+    },
+    DurableMailbox {
+        #[arg()]
+        instance_name: String,
+    },
+    VolatileMailbox {
+        #[arg()]
+        instance_name: String,
+    },
+    LimitMailbox {
+        #[arg()]
+        instance_name: String,
+        #[arg()]
+        capacity: usize,
+        #[arg()]
+        overflow: String,
+    },
+    UnlimitMailbox {
+        #[arg()]
+        instance_name: String,
+    },
+    LimitFuel {
+        #[arg()]
+        instance_name: String,
+        #[arg()]
+        fuel: u64,
+    },
+    UnlimitFuel {
+        #[arg()]
+        instance_name: String,
+    },
+    SetDeadline {
+        #[arg()]
+        instance_name: String,
+        #[arg()]
+        milliseconds: u64,
+    },
+    ClearDeadline {
+        #[arg()]
+        instance_name: String,
+    },
+    SetHibernation {
+        #[arg()]
+        instance_name: String,
+        #[arg()]
+        milliseconds: u64,
+    },
+    ClearHibernation {
+        #[arg()]
+        instance_name: String,
+    },
+    LimitMemory {
+        #[arg()]
+        instance_name: String,
+        #[arg()]
+        pages: u32,
+    },
+    UnlimitMemory {
+        #[arg()]
+        instance_name: String,
+    },
+    ExactlyOnce {
+        #[arg()]
+        instance_name: String,
+    },
+    AtLeastOnce {
+        #[arg()]
+        instance_name: String,
+    },
+    ListDeadLetters {},
+    InspectDeadLetter {
+        #[arg()]
+        id: i64,
+    },
+    ReplayDeadLetter {
+        #[arg()]
+        id: i64,
+        #[arg()]
+        destination: Option<String>,
+    },
+    PurgeDeadLetters {},
 }
 
 #[tokio::main]
@@ -61,14 +216,24 @@ async fn main() -> othismo::Result<()> {
         let mut image = Image::open(image_name.clone() + ".simg")?;
 
         match command.sub_command {
-            Some(SubCommands::ImportModule { module_name }) => {
-                let module = std::fs::read(&module_name)?;
-                let module_namespace_name = &std::path::Path::new(&module_name)
-                    .file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap();
-                image.import_object(&module_namespace_name, Object::new_module(&module)?)?;
+            Some(SubCommands::ImportModule {
+                module_name,
+                destination,
+                replace,
+            }) => {
+                let imports = image.import_modules(
+                    std::path::Path::new(&module_name),
+                    destination.as_deref(),
+                    replace,
+                )?;
+                for import in imports {
+                    println!(
+                        "{}\t{}\t{:?}",
+                        import.file.display(),
+                        import.name,
+                        import.outcome
+                    );
+                }
             }
             Some(SubCommands::RemoveModule { module_name }) => {
                 image.remove_object(&module_name)?;
@@ -90,21 +255,254 @@ async fn main() -> othismo::Result<()> {
                 image.remove_object(&instance_name)?;
             }
             Some(SubCommands::SendMessage { instance_name }) => {
-                let mut namespace: Namespace = image.into();
-                let document = doc! { "othismo": { "send_to": "foobar" } };
-                let mut bytes: Vec<u8> = Vec::new();
-                document.to_writer(&mut bytes).unwrap();
-                namespace.send_message(&instance_name, Message::new(bytes));
+                let mut namespace = Namespace::try_from(image)?;
+                let destination = NamespacePath::parse(&instance_name)?;
+                namespace.send_document(&destination, doc! {});
                 namespace.wait_for_idleness(Duration::from_secs(30)).await;
+                for (path, usage) in namespace.fuel_usage() {
+                    println!(
+                        "{}\t{} messages\t{} fuel burnt\t{} of {} left\t{} ran out",
+                        path,
+                        usage.messages,
+                        usage.burnt,
+                        usage.last_remaining,
+                        usage.budget,
+                        usage.exhausted
+                    );
+                }
             }
             Some(SubCommands::NewImage { image_name: _ }) => {
                 eprintln!("Specify the image name _after_ the new-image command");
             }
             Some(SubCommands::ListObjects {}) => {
-                for name in image.list_objects("")? {
+                for name in image.list_objects(&NamespacePath::root())? {
                     println!("{}", name);
                 }
             }
+            Some(SubCommands::Inspect { name }) => {
+                let object = image.get_object(&name)?;
+
+                println!(
+                    "{}\t{}\t{} bytes",
+                    name,
+                    object.as_kind_str(),
+                    object.to_bytes()?.len()
+                );
+                match object.source()? {
+                    Some(source) => println!("\n{}", source),
+                    None => println!("\n(no source recorded)"),
+                }
+            }
+            Some(SubCommands::History { name }) => {
+                for revision in image.history(&name)? {
+                    println!(
+                        "{}\t{}\t{} bytes{}",
+                        revision.revision,
+                        revision.revised_at,
+                        revision.size,
+                        if revision.current { "\t(current)" } else { "" }
+                    );
+                }
+            }
+            Some(SubCommands::Revert { name, revision }) => {
+                image.revert_object(&name, revision)?;
+            }
+            Some(SubCommands::SetHistoryRetention { revisions }) => {
+                image.set_history_retention(revisions)?;
+            }
+            Some(SubCommands::Grant {
+                path,
+                principal,
+                permission,
+            }) => {
+                image.grant(&path, &principal, Permission::parse(&permission)?)?;
+            }
+            Some(SubCommands::Revoke {
+                path,
+                principal,
+                permission,
+            }) => {
+                image.revoke(&path, &principal, Permission::parse(&permission)?)?;
+            }
+            Some(SubCommands::ListGrants {}) => {
+                for rule in image.access_rules()? {
+                    println!("{}\t{}\t{}", rule.path, rule.permission, rule.principal);
+                }
+            }
+            Some(SubCommands::MintCapability { target, holder }) => {
+                let capability = image.mint_capability(&target, &holder)?;
+                println!("{}", capability.token);
+            }
+            Some(SubCommands::RevokeCapability { token }) => {
+                image.revoke_capability(&token)?;
+            }
+            Some(SubCommands::ListCapabilities {}) => {
+                for grant in image.capability_grants()? {
+                    println!(
+                        "{}\t{}\t{}{}",
+                        grant.holder,
+                        grant.capability.target,
+                        grant.capability.token,
+                        if grant.delivered {
+                            ""
+                        } else {
+                            "\t(undelivered)"
+                        }
+                    );
+                }
+            }
+            Some(SubCommands::Sandbox { instance_name }) => {
+                image.set_sandboxed(&instance_name, true)?;
+            }
+            Some(SubCommands::Unsandbox { instance_name }) => {
+                image.set_sandboxed(&instance_name, false)?;
+            }
+            Some(SubCommands::Supervise {
+                instance_name,
+                policy,
+                max_restarts,
+                within_seconds,
+            }) => {
+                let supervision = Supervision {
+                    policy: RestartPolicy::parse(&policy)?,
+                    max_restarts,
+                    within: Duration::from_secs(within_seconds),
+                };
+                image.set_supervision(&instance_name, &supervision)?;
+            }
+            Some(SubCommands::DurableMailbox { instance_name }) => {
+                image.set_durable_mailbox(&instance_name, true)?;
+            }
+            Some(SubCommands::VolatileMailbox { instance_name }) => {
+                image.set_durable_mailbox(&instance_name, false)?;
+            }
+            Some(SubCommands::LimitMailbox {
+                instance_name,
+                capacity,
+                overflow,
+            }) => {
+                let limit = MailboxLimit {
+                    capacity,
+                    overflow: Overflow::parse(&overflow)?,
+                };
+                image.set_mailbox_limit(&instance_name, Some(limit))?;
+            }
+            Some(SubCommands::UnlimitMailbox { instance_name }) => {
+                image.set_mailbox_limit(&instance_name, None)?;
+            }
+            Some(SubCommands::LimitFuel {
+                instance_name,
+                fuel,
+            }) => {
+                image.set_fuel_budget(&instance_name, Some(fuel))?;
+            }
+            Some(SubCommands::UnlimitFuel { instance_name }) => {
+                image.set_fuel_budget(&instance_name, None)?;
+            }
+            Some(SubCommands::SetDeadline {
+                instance_name,
+                milliseconds,
+            }) => {
+                let deadline = Duration::from_millis(milliseconds);
+                image.set_execution_deadline(&instance_name, Some(deadline))?;
+            }
+            Some(SubCommands::ClearDeadline { instance_name }) => {
+                image.set_execution_deadline(&instance_name, None)?;
+            }
+            Some(SubCommands::SetHibernation {
+                instance_name,
+                milliseconds,
+            }) => {
+                let period = Duration::from_millis(milliseconds);
+                image.set_hibernation_period(&instance_name, Some(period))?;
+            }
+            Some(SubCommands::ClearHibernation { instance_name }) => {
+                image.set_hibernation_period(&instance_name, None)?;
+            }
+            Some(SubCommands::LimitMemory {
+                instance_name,
+                pages,
+            }) => {
+                image.set_memory_limit(&instance_name, Some(pages))?;
+            }
+            Some(SubCommands::UnlimitMemory { instance_name }) => {
+                image.set_memory_limit(&instance_name, None)?;
+            }
+            Some(SubCommands::ExactlyOnce { instance_name }) => {
+                image.set_exactly_once(&instance_name, true)?;
+            }
+            Some(SubCommands::AtLeastOnce { instance_name }) => {
+                image.set_exactly_once(&instance_name, false)?;
+            }
+            Some(SubCommands::ListDeadLetters {}) => {
+                for dead_letter in image.dead_letters()? {
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}",
+                        dead_letter.id,
+                        dead_letter.received_at,
+                        dead_letter.destination.as_deref().unwrap_or("(capability)"),
+                        dead_letter.sender.as_deref().unwrap_or("(host)"),
+                        dead_letter.code
+                    );
+                }
+            }
+            Some(SubCommands::InspectDeadLetter { id }) => {
+                let dead_letter = image.dead_letter(id)?;
+
+                println!("received at:\t{}", dead_letter.received_at);
+                println!(
+                    "destination:\t{}",
+                    dead_letter.destination.as_deref().unwrap_or("(capability)")
+                );
+                println!(
+                    "sender:\t\t{}",
+                    dead_letter.sender.as_deref().unwrap_or("(host)")
+                );
+                println!("reason:\t\t{}: {}", dead_letter.code, dead_letter.reason);
+                match bson::Document::from_reader(dead_letter.message.as_slice()) {
+                    Ok(document) => println!("\n{}", document),
+                    Err(_) => println!("\n({} bytes, not BSON)", dead_letter.message.len()),
+                }
+            }
+            Some(SubCommands::Serve { checkpoint_every }) => {
+                image.lock_exclusively()?;
+                let namespace = Namespace::try_from(image)?;
+                let every = Duration::from_secs(checkpoint_every);
+                println!(
+                    "serving {}.simg, checkpointing every {:?}",
+                    image_name, every
+                );
+
+                let mut checkpoints = tokio::time::interval(every);
+                checkpoints.tick().await;
+                let shutdown = shutdown_signal();
+                tokio::pin!(shutdown);
+                loop {
+                    tokio::select! {
+                        _ = checkpoints.tick() => checkpoint(&namespace, every).await,
+                        signalled = &mut shutdown => {
+                            signalled?;
+                            break;
+                        }
+                    }
+                }
+
+                // Let whatever's under way finish, then keep where every instance got to
+                println!("shutting down ... draining");
+                namespace.wait_for_idleness(Duration::from_secs(30)).await;
+                if !namespace.is_idle() {
+                    eprintln!("still busy after 30s, shutting down anyway");
+                }
+                checkpoint(&namespace, Duration::from_secs(30)).await;
+            }
+            Some(SubCommands::ReplayDeadLetter { id, destination }) => {
+                let namespace = Namespace::try_from(image)?;
+                namespace.replay_dead_letter(id, destination.as_deref())?;
+                namespace.wait_for_idleness(Duration::from_secs(30)).await;
+            }
+            Some(SubCommands::PurgeDeadLetters {}) => {
+                println!("{} dead letters purged", image.purge_dead_letters()?);
+            }
             None => {
                 eprintln!("No sub command specified");
             }
@@ -125,3 +523,36 @@ async fn main() -> othismo::Result<()> {
 
     Ok(())
 }
+
+/// Checkpoints every running instance, giving up on those that haven't within `within`.
+async fn checkpoint(namespace: &Namespace, within: Duration) {
+    match tokio::time::timeout(within, namespace.checkpoint()).await {
+        Ok(checkpointed) => {
+            for (path, saved) in checkpointed {
+                match saved {
+                    Ok(()) => println!("checkpointed {}", path),
+                    Err(error) => eprintln!("couldn't checkpoint {}, {:?}", path, error),
+                }
+            }
+        }
+        Err(_) => eprintln!("gave up on checkpointing after {:?}", within),
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM where there's such a thing.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    let terminated = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        signal(SignalKind::terminate())?.recv().await;
+        Ok::<_, std::io::Error>(())
+    };
+    #[cfg(not(unix))]
+    let terminated = std::future::pending::<std::io::Result<()>>();
+
+    tokio::select! {
+        interrupted = tokio::signal::ctrl_c() => interrupted,
+        terminated = terminated => terminated,
+    }
+}
@@ -0,0 +1,89 @@
+use super::namespace_path::NamespacePath;
+use super::{OthismoError, Result};
+use std::fmt;
+
+/// Grants to `*` cover every sender.
+pub const ANYONE: &str = "*";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Permission {
+    Send,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Send => "SEND",
+        }
+    }
+
+    pub fn parse(permission: &str) -> Result<Permission> {
+        match permission.to_ascii_uppercase().as_str() {
+            "SEND" => Ok(Permission::Send),
+            _ => Err(OthismoError::UnknownPermission(permission.to_string()))?,
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Lets `principal` (a process, every process under a path, or `*`) use `permission` on `path`
+/// and everything beneath it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessRule {
+    pub path: NamespacePath,
+    pub principal: String,
+    pub permission: Permission,
+}
+
+impl AccessRule {
+    fn admits(&self, sender: &NamespacePath) -> bool {
+        self.principal == ANYONE
+            || NamespacePath::parse(&self.principal).is_ok_and(|principal| principal.covers(sender))
+    }
+}
+
+/// Paths are open until something is granted on them; after that only the grants on the
+/// closest path with any grants for that permission count. Messages from the host are trusted.
+#[derive(Debug, Clone, Default)]
+pub struct AccessControl {
+    rules: Vec<AccessRule>,
+}
+
+impl AccessControl {
+    pub fn new(rules: Vec<AccessRule>) -> AccessControl {
+        AccessControl { rules }
+    }
+
+    pub fn allows(
+        &self,
+        sender: Option<&NamespacePath>,
+        permission: Permission,
+        target: &NamespacePath,
+    ) -> bool {
+        let Some(sender) = sender else {
+            return true;
+        };
+
+        let covering: Vec<&AccessRule> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.permission == permission && rule.path.covers(target))
+            .collect();
+        let Some(closest) = covering.iter().map(|rule| rule.path.as_str().len()).max() else {
+            return true;
+        };
+
+        covering
+            .iter()
+            .filter(|rule| rule.path.as_str().len() == closest)
+            .any(|rule| rule.admits(sender))
+    }
+}
+
+#[cfg(test)]
+mod tests;
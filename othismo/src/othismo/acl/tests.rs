@@ -0,0 +1,73 @@
+use crate::othismo::acl::{AccessControl, AccessRule, Permission};
+use crate::othismo::namespace_path::NamespacePath;
+
+fn path(path: &str) -> NamespacePath {
+    NamespacePath::parse(path).unwrap()
+}
+
+fn rule(on: &str, principal: &str, permission: Permission) -> AccessRule {
+    AccessRule {
+        path: path(on),
+        principal: principal.to_string(),
+        permission,
+    }
+}
+
+#[test]
+fn paths_without_grants_are_open() {
+    let access = AccessControl::new(vec![rule("/locked", "/friend", Permission::Send)]);
+
+    assert!(access.allows(Some(&path("/stranger")), Permission::Send, &path("/open")));
+}
+
+#[test]
+fn grants_cover_the_path_and_everything_beneath_it() {
+    let access = AccessControl::new(vec![rule("/locked", "/friends", Permission::Send)]);
+
+    assert!(access.allows(
+        Some(&path("/friends/a")),
+        Permission::Send,
+        &path("/locked/inner")
+    ));
+    assert!(!access.allows(
+        Some(&path("/stranger")),
+        Permission::Send,
+        &path("/locked/inner")
+    ));
+    assert!(!access.allows(
+        Some(&path("/friendship")),
+        Permission::Send,
+        &path("/locked")
+    ));
+}
+
+#[test]
+fn the_closest_grants_win() {
+    let access = AccessControl::new(vec![
+        rule("/apps", "*", Permission::Send),
+        rule("/apps/vault", "/apps/teller", Permission::Send),
+    ]);
+
+    assert!(access.allows(
+        Some(&path("/anyone")),
+        Permission::Send,
+        &path("/apps/shop")
+    ));
+    assert!(!access.allows(
+        Some(&path("/anyone")),
+        Permission::Send,
+        &path("/apps/vault")
+    ));
+    assert!(access.allows(
+        Some(&path("/apps/teller")),
+        Permission::Send,
+        &path("/apps/vault")
+    ));
+}
+
+#[test]
+fn the_host_is_trusted() {
+    let access = AccessControl::new(vec![rule("/locked", "/friend", Permission::Send)]);
+
+    assert!(access.allows(None, Permission::Send, &path("/locked")));
+}
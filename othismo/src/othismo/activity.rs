@@ -0,0 +1,71 @@
+use super::Message;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Notify;
+
+/// Counts the work going on in a namespace, much like a wait group: messages waiting to be
+/// routed, messages waiting in mailboxes, messages processes are handling & exits the
+/// supervisor hasn't dealt with yet. The namespace is idle once there's none.
+#[derive(Debug, Default)]
+pub struct Activity {
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+impl Activity {
+    pub fn add(&self, count: usize) {
+        self.in_flight.fetch_add(count, Ordering::SeqCst);
+    }
+
+    pub fn done(&self, count: usize) {
+        if count > 0 && self.in_flight.fetch_sub(count, Ordering::SeqCst) == count {
+            self.idle.notify_waiters();
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.in_flight() == 0
+    }
+
+    /// Returns as soon as there's nothing going on.
+    pub async fn idle(&self) {
+        loop {
+            let notified = self.idle.notified();
+            tokio::pin!(notified);
+            // Registered before checking, so going idle in between isn't missed
+            notified.as_mut().enable();
+            if self.is_idle() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Where processes & the namespace send messages to be routed; each one counts as activity
+/// until the router's dealt with it.
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    tx: UnboundedSender<Message>,
+    activity: Arc<Activity>,
+}
+
+impl Outbox {
+    pub fn new(tx: UnboundedSender<Message>, activity: Arc<Activity>) -> Outbox {
+        Outbox { tx, activity }
+    }
+
+    pub fn send(&self, message: Message) -> Result<(), SendError<Message>> {
+        self.activity.add(1);
+        self.tx.send(message).inspect_err(|_| self.activity.done(1))
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,55 @@
+use crate::othismo::activity::{Activity, Outbox};
+use crate::othismo::{Channel, Message};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn waiting_for_idleness_returns_as_soon_as_the_work_is_done() {
+    let activity = Arc::new(Activity::default());
+    activity.add(2);
+
+    let waiting = tokio::spawn({
+        let activity = activity.clone();
+        async move { activity.idle().await }
+    });
+    activity.done(1);
+    tokio::task::yield_now().await;
+    assert!(!waiting.is_finished());
+
+    activity.done(1);
+    tokio::time::timeout(Duration::from_millis(100), waiting)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn nothing_to_do_is_idle_straight_away() {
+    let activity = Activity::default();
+
+    tokio::time::timeout(Duration::from_millis(100), activity.idle())
+        .await
+        .unwrap();
+}
+
+#[test]
+fn messages_sent_count_until_they_are_dealt_with() {
+    let activity = Arc::new(Activity::default());
+    let (tx, _rx) = Channel::new().split();
+    let outbox = Outbox::new(tx, activity.clone());
+
+    outbox.send(Message::new(vec![1])).unwrap();
+    assert_eq!(activity.in_flight(), 1);
+}
+
+#[test]
+fn messages_nobody_can_receive_do_not_count() {
+    let activity = Arc::new(Activity::default());
+    let (tx, rx) = Channel::new().split();
+    drop(rx);
+
+    assert!(Outbox::new(tx, activity.clone())
+        .send(Message::new(vec![1]))
+        .is_err());
+    assert!(activity.is_idle());
+}
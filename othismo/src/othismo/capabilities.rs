@@ -0,0 +1,87 @@
+use super::namespace_path::NamespacePath;
+use bson::{doc, Document};
+use std::collections::{HashMap, HashSet};
+
+const TOKEN_BYTES: usize = 16;
+
+/// An unforgeable token that lets whoever holds it send to `target`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capability {
+    pub token: String,
+    pub target: NamespacePath,
+}
+
+impl Capability {
+    pub fn to_document(&self) -> Document {
+        doc! { "token": &self.token, "target": self.target.as_str() }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Grant {
+    target: NamespacePath,
+    holders: HashSet<NamespacePath>,
+}
+
+/// The capabilities the namespace has minted, who holds them, and which processes are
+/// sandboxed, i.e. may only send through capabilities they've been given.
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    grants: HashMap<String, Grant>,
+    sandboxed: HashSet<NamespacePath>,
+}
+
+impl Capabilities {
+    pub fn mint_token() -> String {
+        let mut bytes = [0u8; TOKEN_BYTES];
+        getrandom::getrandom(&mut bytes).expect("No source of randomness for capability tokens");
+
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    pub fn insert(&mut self, capability: Capability) {
+        self.grants.entry(capability.token).or_insert(Grant {
+            target: capability.target,
+            holders: HashSet::new(),
+        });
+    }
+
+    pub fn give(&mut self, token: &str, holder: &NamespacePath) -> bool {
+        match self.grants.get_mut(token) {
+            Some(grant) => {
+                grant.holders.insert(holder.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn revoke(&mut self, token: &str) -> bool {
+        self.grants.remove(token).is_some()
+    }
+
+    /// The target of `token`, provided `holder` was given it; the host may use any token.
+    pub fn target(&self, token: &str, holder: Option<&NamespacePath>) -> Option<&NamespacePath> {
+        let grant = self.grants.get(token)?;
+
+        match holder {
+            Some(holder) if !grant.holders.contains(holder) => None,
+            _ => Some(&grant.target),
+        }
+    }
+
+    pub fn set_sandboxed(&mut self, process: &NamespacePath, sandboxed: bool) {
+        if sandboxed {
+            self.sandboxed.insert(process.clone());
+        } else {
+            self.sandboxed.remove(process);
+        }
+    }
+
+    pub fn is_sandboxed(&self, process: Option<&NamespacePath>) -> bool {
+        process.is_some_and(|process| self.sandboxed.contains(process))
+    }
+}
+
+#[cfg(test)]
+mod tests;
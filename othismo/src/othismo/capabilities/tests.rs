@@ -0,0 +1,49 @@
+use crate::othismo::capabilities::{Capabilities, Capability};
+use crate::othismo::namespace_path::NamespacePath;
+
+fn path(path: &str) -> NamespacePath {
+    NamespacePath::parse(path).unwrap()
+}
+
+fn capabilities_for(target: &str, holder: &str) -> (Capabilities, String) {
+    let mut capabilities = Capabilities::default();
+    let token = Capabilities::mint_token();
+    capabilities.insert(Capability {
+        token: token.clone(),
+        target: path(target),
+    });
+    capabilities.give(&token, &path(holder));
+
+    (capabilities, token)
+}
+
+#[test]
+fn tokens_are_unguessable() {
+    let first = Capabilities::mint_token();
+
+    assert_eq!(first.len(), 32);
+    assert_ne!(first, Capabilities::mint_token());
+}
+
+#[test]
+fn only_holders_can_use_a_capability() {
+    let (capabilities, token) = capabilities_for("/service", "/holder");
+
+    assert_eq!(
+        capabilities.target(&token, Some(&path("/holder"))),
+        Some(&path("/service"))
+    );
+    assert_eq!(capabilities.target(&token, Some(&path("/other"))), None);
+    assert_eq!(capabilities.target(&token, None), Some(&path("/service")));
+    assert_eq!(capabilities.target("forged", Some(&path("/holder"))), None);
+}
+
+#[test]
+fn revoked_capabilities_stop_working() {
+    let (mut capabilities, token) = capabilities_for("/service", "/holder");
+
+    assert!(capabilities.revoke(&token));
+
+    assert_eq!(capabilities.target(&token, Some(&path("/holder"))), None);
+    assert!(!capabilities.give(&token, &path("/holder")));
+}
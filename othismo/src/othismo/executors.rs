@@ -1,15 +1,40 @@
 use crate::othismo;
-use crate::othismo::image::InstanceAtRest;
+use crate::othismo::activity::Outbox;
+use crate::othismo::fuel::FuelGauge;
+use crate::othismo::image::{Image, InstanceAtRest, Object};
+use crate::othismo::mailbox::Mailbox;
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::supervision::{event, Events, MEMORY_LIMIT_REACHED};
+use crate::othismo::tunables::{LimitingTunables, RefusedGrowths};
+use crate::othismo::OthismoError;
+use bson::oid::ObjectId;
 use bson::{doc, to_bson, Document};
+use std::collections::HashMap;
 use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::task::Poll;
-use tokio::sync::mpsc::{error::TryRecvError, UnboundedSender};
+use std::time::Duration;
+use tokio::sync::mpsc::{error::TryRecvError, unbounded_channel, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use wasmbin::indices::FuncId;
+use wasmbin::types::RefType;
+use wasmer::sys::{BaseTunables, EngineBuilder, Features, NativeEngineExt};
+use wasmer::wasmparser::Operator;
 use wasmer::{
-    imports, Function, FunctionEnv, FunctionEnvMut, Instance, Memory, Store, TypedFunction,
+    imports, CompilerConfig, Cranelift, Function, FunctionEnv, Instance, Memory, Pages, Store,
+    Target, TypedFunction, Value,
 };
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
+use wasmer_middlewares::Metering;
 
-use super::{Message, ProcessCtx, ProcessExecutor};
+use super::{
+    error_document, is_error, reply_address, CheckpointRequest, Checkpoints, Message, ProcessCtx,
+    ProcessExecutor, TIMED_OUT,
+};
 
 pub struct ConsoleExecutor;
 pub struct ConsoleTask {
@@ -30,22 +55,24 @@ impl Future for ConsoleTask {
         this.ctx.fill_waker_slot(cx.waker().clone());
 
         println!("Polling Console");
-        return match this.ctx.inbox.poll_recv(cx) {
-            Poll::Ready(Some(message)) => {
-                let document = Document::from_reader(&mut message.bytes.as_slice()).unwrap();
-                println!("{}", document);
-                print!("...pending, message");
-                Poll::Pending
-            }
-            Poll::Ready(None) => {
-                println!("...ready");
-                Poll::Ready(())
-            }
-            Poll::Pending => {
-                println!("...pending, no message");
-                Poll::Pending
+        // Until it's asked for another message the console counts as busy with the last one
+        loop {
+            match this.ctx.inbox.poll_recv(cx) {
+                Poll::Ready(Some(message)) => {
+                    let document = Document::from_reader(&mut message.bytes.as_slice()).unwrap();
+                    println!("{}", document);
+                    print!("...pending, message");
+                }
+                Poll::Ready(None) => {
+                    println!("...ready");
+                    return Poll::Ready(());
+                }
+                Poll::Pending => {
+                    println!("...pending, no message");
+                    return Poll::Pending;
+                }
             }
-        };
+        }
     }
 }
 
@@ -75,7 +102,10 @@ impl Future for EchoTask {
                 Ok(message) => {
                     let document = Document::from_reader(&mut message.bytes.as_slice()).unwrap();
                     let othismo = document.get_document("othismo").unwrap();
-                    let reply_to = othismo.get_str("reply_to").unwrap();
+                    let reply_to = othismo
+                        .get_str("reply_to")
+                        .or_else(|_| othismo.get_str("sent_from"))
+                        .unwrap();
                     let response_id = othismo.get_i64("request_id").unwrap();
 
                     let mut response = doc! {
@@ -93,7 +123,7 @@ impl Future for EchoTask {
 
                     response.to_writer(&mut buffer).unwrap();
 
-                    this.ctx.outbox.send(Message { bytes: buffer });
+                    this.ctx.send(Message::new(buffer));
                 }
                 Err(reason) => match reason {
                     TryRecvError::Empty => return Poll::Pending,
@@ -107,27 +137,254 @@ impl Future for EchoTask {
 
 pub struct InstanceExecutor {
     instance_at_rest: InstanceAtRest,
+    mailbox: Option<DurableMailbox>,
+    fuel: Option<Arc<FuelGauge>>,
+    deadline: Option<Duration>,
+    memory_limit: Option<Pages>,
+    events: Option<Events>,
+    image: Option<Arc<Mutex<Image>>>,
+    hibernate_after: Option<Duration>,
 }
+/// An instance & everything it needs to handle a message, which lives on its `GuestThread`.
 pub struct InstanceTask {
-    ctx: ProcessCtx,
+    name: NamespacePath,
+    outbox: Outbox,
+    instance_at_rest: InstanceAtRest,
     instance: Instance,
     store: Store,
+    env: FunctionEnv<InstanceEnv>,
+    mailbox: Option<DurableMailbox>,
+    fuel: Option<Arc<FuelGauge>>,
+    deadline: Option<Duration>,
+    /// Set once the task's been given up on, so whatever it's still doing is kept to itself.
+    cancelled: Arc<AtomicBool>,
+    refused: RefusedGrowths,
+    events: Option<Events>,
+}
+
+/// The metering middleware exports its own globals, which aren't part of the instance at rest.
+const METERING_GLOBALS_PREFIX: &str = "wasmer_metering_";
+
+/// How many points each millisecond of a deadline meters a guest with. It's generous, so the
+/// deadline itself normally goes first, but a call that's been given up on still runs dry soon
+/// after even if it never calls into the host again.
+const POINTS_PER_DEADLINE_MILLISECOND: u64 = 10_000_000;
+
+/// What a guest may burn handling a message: its fuel budget, or what its deadline allows if
+/// that's less.
+fn metering_budget(fuel: Option<&FuelGauge>, deadline: Option<Duration>) -> Option<u64> {
+    let deadline = deadline.map(|deadline| {
+        let milliseconds = u64::try_from(deadline.as_millis()).unwrap_or(u64::MAX);
+        milliseconds
+            .max(1)
+            .saturating_mul(POINTS_PER_DEADLINE_MILLISECOND)
+    });
+
+    match (fuel.map(FuelGauge::budget), deadline) {
+        (Some(fuel), Some(deadline)) => Some(fuel.min(deadline)),
+        (fuel, deadline) => fuel.or(deadline),
+    }
+}
+
+/// Where an instance with a durable mailbox checkpoints itself & acknowledges what it's handled.
+/// Exactly-once instances also skip mail they've already handled, and hold on to what they send
+/// until it's checkpointed along with everything else.
+#[derive(Clone)]
+pub struct DurableMailbox {
+    image: Arc<Mutex<Image>>,
+    path: NamespacePath,
+    exactly_once: bool,
+}
+
+impl DurableMailbox {
+    pub fn new(image: Arc<Mutex<Image>>, path: NamespacePath) -> Self {
+        DurableMailbox {
+            image,
+            path,
+            exactly_once: false,
+        }
+    }
+
+    pub fn exactly_once(mut self) -> Self {
+        self.exactly_once = true;
+        self
+    }
+
+    fn has_processed(&self, message_id: &str) -> bool {
+        let image = self.image.lock().unwrap();
+        image.has_processed(&self.path, message_id).unwrap_or(false)
+    }
+
+    /// Saves `snapshot` as the instance and acknowledges mail `key` in one go, so a message is
+    /// only forgotten once what it did is kept. With a `message_id` it's also marked processed,
+    /// and the `outgoing` messages it led to are kept until the router has them. Nothing's kept
+    /// once the task's been `cancelled`, which is checked under the image's lock.
+    fn checkpoint(
+        &self,
+        key: i64,
+        snapshot: Option<InstanceAtRest>,
+        message_id: Option<&str>,
+        outgoing: &mut [Message],
+        cancelled: &AtomicBool,
+    ) -> othismo::Result<bool> {
+        self.image.lock().unwrap().transaction(|image| {
+            if cancelled.load(Ordering::SeqCst) {
+                return Ok(false);
+            }
+            if let Some(snapshot) = snapshot {
+                image.replace_object(self.path.as_str(), Object::Instance(snapshot))?;
+            }
+            if let Some(message_id) = message_id {
+                image.mark_processed(&self.path, message_id)?;
+            }
+            for message in outgoing.iter_mut() {
+                let message_id = ObjectId::new().to_hex();
+                message.outgoing_key =
+                    Some(image.hold_outgoing_mail(&self.path, &message_id, &message.bytes)?);
+                message.message_id = Some(message_id);
+            }
+            image.acknowledge_mail(key)?;
+
+            Ok(true)
+        })
+    }
+
+    /// Acknowledges mail that was given up on, without keeping anything it did.
+    fn abandon(&self, key: i64) -> othismo::Result<()> {
+        self.image.lock().unwrap().acknowledge_mail(key)
+    }
 }
+
 pub struct InstanceEnv {
+    name: NamespacePath,
     memory: Option<Memory>,
-    outbox: UnboundedSender<Message>,
+    outbox: Outbox,
+    /// While set, what the guest sends is held here instead of going straight out.
+    held: Option<Vec<Message>>,
+    /// Once set, the guest is trapped the next time it calls into the host.
+    cancelled: Arc<AtomicBool>,
 }
 
-impl ProcessExecutor for InstanceExecutor {
-    fn start(self, context: ProcessCtx) -> Pin<Box<dyn Future<Output = ()> + Send>> {
-        let mut store = Store::default();
-        let buffer = self.instance_at_rest.to_bytes();
-        let wasmer_instance_module = wasmer::Module::new(&mut store, &buffer).unwrap();
+impl InstanceEnv {
+    fn send(&mut self, message: Message) {
+        match &mut self.held {
+            Some(held) => held.push(message),
+            None => self.outbox.send(message).unwrap(),
+        }
+    }
+}
+
+struct LoadedInstance {
+    instance: Instance,
+    store: Store,
+    env: FunctionEnv<InstanceEnv>,
+    cancelled: Arc<AtomicBool>,
+    refused: RefusedGrowths,
+}
+
+impl InstanceExecutor {
+    fn new_store(&self, refused: &RefusedGrowths) -> Store {
+        let mut features = Features::new();
+        features.multi_memory(true);
+
+        let mut compiler = Cranelift::default();
+        if let Some(budget) = metering_budget(self.fuel.as_deref(), self.deadline) {
+            compiler.push_middleware(Arc::new(Metering::new(budget, operator_cost)));
+        }
+
+        let mut engine = EngineBuilder::new(compiler)
+            .set_features(Some(features))
+            .engine();
+        if let Some(limit) = self.memory_limit {
+            let base = BaseTunables::for_target(&Target::default());
+            engine.set_tunables(LimitingTunables::new(base, limit, refused.clone()));
+        }
+
+        Store::new(engine)
+    }
+
+    pub fn with_mailbox(mut self, mailbox: DurableMailbox) -> Self {
+        self.mailbox = Some(mailbox);
+        self
+    }
+
+    /// Meters the instance, trapping any message that burns through the gauge's budget.
+    pub fn with_fuel(mut self, fuel: Arc<FuelGauge>) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Gives up on any message the guest takes longer than `deadline` to handle, answering it
+    /// with `timed_out`. That's no interrupt: wasmer can't stop a call from outside, so the call
+    /// carries on detached until its next call into the host traps it, or until it burns
+    /// through the points the deadline meters it with. What it burns still counts against the
+    /// instance's fuel gauge, if it has one.
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Caps each of the instance's memories at `pages`, whatever its module declares.
+    pub fn with_memory_limit(mut self, pages: u32) -> Self {
+        self.memory_limit = Some(Pages(pages));
+        self
+    }
+
+    /// Where the instance reports what happens to it, like growths it was refused.
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Where the instance saves itself when it's checkpointed or hibernates.
+    pub fn with_image(mut self, image: Arc<Mutex<Image>>) -> Self {
+        self.image = Some(image);
+        self
+    }
+
+    /// Saves the instance & lets go of it once it's gone `after` without mail, instead of
+    /// keeping it loaded. It's only saved, and so only hibernates, with an image.
+    pub fn with_hibernation(mut self, after: Duration) -> Self {
+        self.hibernate_after = Some(after);
+        self
+    }
+
+    fn into_task(
+        self,
+        name: NamespacePath,
+        outbox: Outbox,
+        loaded: LoadedInstance,
+    ) -> InstanceTask {
+        InstanceTask {
+            name,
+            outbox,
+            instance_at_rest: self.instance_at_rest,
+            instance: loaded.instance,
+            store: loaded.store,
+            env: loaded.env,
+            mailbox: self.mailbox,
+            fuel: self.fuel,
+            deadline: self.deadline,
+            cancelled: loaded.cancelled,
+            refused: loaded.refused,
+            events: self.events,
+        }
+    }
+
+    fn load(&self, name: &NamespacePath, outbox: &Outbox) -> othismo::Result<LoadedInstance> {
+        let refused = RefusedGrowths::default();
+        let mut store = self.new_store(&refused);
+        let buffer = self.instance_at_rest.to_bytes()?;
+        let wasmer_instance_module = wasmer::Module::new(&mut store, &buffer)?;
+        let cancelled = Arc::new(AtomicBool::new(false));
         let env = FunctionEnv::new(
             &mut store,
             InstanceEnv {
+                name: name.clone(),
                 memory: None,
-                outbox: context.outbox.clone(),
+                outbox: outbox.clone(),
+                held: None,
+                cancelled: cancelled.clone(),
             },
         );
 
@@ -142,60 +399,116 @@ impl ProcessExecutor for InstanceExecutor {
                     "_send_message" => send_message_trampoline,
                 }
             },
-        )
-        .unwrap();
-
-        env.as_mut(&mut store).memory = Some(
-            wasmer_instance
-                .exports
-                .get_memory("memory")
-                .unwrap()
-                .clone(),
-        );
+        )?;
+
+        env.as_mut(&mut store).memory = Some(wasmer_instance.exports.get_memory("memory")?.clone());
 
-        let task = Box::pin(InstanceTask {
-            ctx: context,
+        Ok(LoadedInstance {
             instance: wasmer_instance,
             store,
-        });
+            env,
+            cancelled,
+            refused,
+        })
+    }
+}
 
-        println!("instance executor running...");
+impl ProcessExecutor for InstanceExecutor {
+    fn start(self, context: ProcessCtx) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(async move {
+            let ProcessCtx {
+                name,
+                inbox,
+                outbox,
+                waker_slot,
+                dormant,
+                checkpoints,
+            } = context;
+            let deadline = self.deadline;
 
-        task
+            match GuestThread::start(self, name.clone(), outbox.clone()).await {
+                Ok(guest) => {
+                    println!("instance executor running...");
+                    if let Some(inbox) = guest.serve(inbox, deadline, &checkpoints).await {
+                        *dormant.lock().unwrap() = Some(inbox);
+                    }
+                }
+                Err(error) => {
+                    UnstartableTask {
+                        ctx: ProcessCtx {
+                            name,
+                            inbox,
+                            outbox,
+                            waker_slot,
+                            dormant,
+                            checkpoints,
+                        },
+                        code: error.code(),
+                        reason: format!("{:?}", error),
+                    }
+                    .await
+                }
+            }
+        })
     }
 }
 
 impl From<InstanceAtRest> for InstanceExecutor {
     fn from(instance_at_rest: InstanceAtRest) -> Self {
-        InstanceExecutor { instance_at_rest }
+        InstanceExecutor {
+            instance_at_rest,
+            mailbox: None,
+            fuel: None,
+            deadline: None,
+            memory_limit: None,
+            events: None,
+            image: None,
+            hibernate_after: None,
+        }
     }
 }
 
 impl InstanceTask {
-    pub fn send_message(mut env: FunctionEnvMut<InstanceEnv>, head: u32, length: u32) -> u32 {
-        println!("native::send_message({}, {})", head, length);
-
-        let (environment, mut store) = env.data_and_store_mut();
-        let view = environment.memory.as_mut().unwrap().view(&store);
-        let mut buffer: Vec<u8> = vec![0; length as usize];
-        view.read(head as u64, buffer.as_mut_slice());
+    /// Hands `message` to the guest, with a full budget of fuel when it's metered. Running
+    /// dry on what its deadline allows rather than its fuel counts as timing out. Whatever the
+    /// call burns is recorded on the fuel gauge, even once it's been given up on.
+    pub fn receive_message(&mut self, message: &[u8]) -> othismo::Result<()> {
+        let fuel = self.fuel.clone();
+        let Some(budget) = metering_budget(fuel.as_deref(), self.deadline) else {
+            return self.call_guest(message);
+        };
 
-        println!(
-            "\"{}\"",
-            String::from_utf8(buffer).unwrap_or("bad_utf8".to_string())
-        );
+        set_remaining_points(&mut self.store, &self.instance, budget);
+        let received = self.call_guest(message);
 
-        return 0;
+        let remaining = get_remaining_points(&mut self.store, &self.instance);
+        match (remaining, fuel) {
+            (MeteringPoints::Remaining(remaining), Some(fuel)) => {
+                fuel.record(fuel.budget() - (budget - remaining));
+                received
+            }
+            (MeteringPoints::Remaining(_), None) => received,
+            (MeteringPoints::Exhausted, Some(fuel)) if fuel.budget() == budget => {
+                fuel.record_exhausted();
+                Err(OthismoError::OutOfFuel.into())
+            }
+            (MeteringPoints::Exhausted, fuel) => {
+                if let Some(fuel) = fuel {
+                    fuel.record(fuel.budget() - budget);
+                }
+                Err(OthismoError::TimedOut.into())
+            }
+        }
     }
 
-    pub fn receive_message(&mut self, message: &[u8]) -> othismo::Result<()> {
+    fn call_guest(&mut self, message: &[u8]) -> othismo::Result<()> {
         let allocate_message: TypedFunction<u32, u32> = self
             .instance
             .exports
             .get_function("_allocate_message")?
             .typed(&self.store)?;
 
-        let message_received: TypedFunction<(u32), ()> = self
+        let message_received: TypedFunction<u32, ()> = self
             .instance
             .exports
             .get_function("_message_received")?
@@ -208,52 +521,557 @@ impl InstanceTask {
         let memory = self.instance.exports.get_memory("memory")?;
         let view = memory.view(&self.store);
 
-        view.write(message_buffer_ptr as u64, message);
+        view.write(message_buffer_ptr as u64, message)?;
 
-        message_received.call(&mut self.store, message_buffer_ptr as u32)?;
+        message_received.call(&mut self.store, message_buffer_ptr)?;
 
         Ok(())
     }
+
+    /// Captures the live globals and memories as a new `InstanceAtRest`, which picks up where
+    /// this instance left off when it's next started.
+    pub fn snapshot(&mut self) -> othismo::Result<InstanceAtRest> {
+        const PAGE_SIZE: usize = 65536;
+        let mut snapshot = self.instance_at_rest.clone();
+
+        // Functions handed out by tables are fresh handles, so they are matched to the exported
+        // functions by their raw `funcref` instead.
+        let mut functions: HashMap<usize, FuncId> = HashMap::new();
+        for (function_id, name) in snapshot.exported_functions()? {
+            let function = self.instance.exports.get_function(&name)?.clone();
+            functions.insert(Self::raw_funcref(&self.store, Some(function)), function_id);
+        }
+
+        let globals: Vec<(String, wasmer::Global)> = self
+            .instance
+            .exports
+            .iter()
+            .globals()
+            .filter(|(name, _)| !name.starts_with(METERING_GLOBALS_PREFIX))
+            .map(|(name, global)| (name.clone(), global.clone()))
+            .collect();
+        for (name, global) in globals {
+            if global.ty(&self.store).mutability.is_mutable() {
+                match global.get(&mut self.store) {
+                    Value::FuncRef(function) => {
+                        let function_id = functions
+                            .get(&Self::raw_funcref(&self.store, function))
+                            .copied();
+                        snapshot.set_exported_reference_global(&name, function_id)?;
+                    }
+                    Value::ExternRef(_) => snapshot.set_exported_reference_global(&name, None)?,
+                    value => snapshot.set_exported_global(&name, value)?,
+                }
+            }
+        }
+
+        snapshot.clear_element_segments()?;
+        for (table_id, name) in snapshot.exported_tables()? {
+            let table = self.instance.exports.get_table(&name)?.clone();
+            let size = table.size(&self.store);
+            snapshot.resize_table(table_id, size)?;
+
+            // Only function references can be written back as element segments
+            if snapshot.table_type(table_id)?.elem_type != RefType::Func {
+                continue;
+            }
+
+            let mut entries = Vec::with_capacity(size as usize);
+            for index in 0..size {
+                let entry = match table.get(&mut self.store, index) {
+                    Some(Value::FuncRef(Some(function))) => {
+                        let raw = Self::raw_funcref(&self.store, Some(function));
+                        functions.get(&raw).copied()
+                    }
+                    _ => None,
+                };
+                entries.push(entry);
+            }
+
+            if entries.iter().any(Option::is_some) {
+                snapshot.add_element_segment(table_id, 0, &entries)?;
+            }
+        }
+
+        snapshot.clear_data_segments()?;
+        for (memory_id, name) in snapshot.exported_memories()? {
+            let memory = self.instance.exports.get_memory(&name)?;
+            let view = memory.view(&self.store);
+            let bytes = view.copy_to_vec()?;
+            snapshot.resize_memory(memory_id, view.data_size())?;
+
+            // Runs of untouched (zeroed) pages don't need a data segment
+            let mut page = 0;
+            while page * PAGE_SIZE < bytes.len() {
+                let is_zeroed = |page: usize| {
+                    bytes[page * PAGE_SIZE..(page + 1) * PAGE_SIZE]
+                        .iter()
+                        .all(|b| *b == 0)
+                };
+
+                if is_zeroed(page) {
+                    page += 1;
+                    continue;
+                }
+
+                let start = page;
+                while page * PAGE_SIZE < bytes.len() && !is_zeroed(page) {
+                    page += 1;
+                }
+
+                snapshot.add_data_segment(
+                    memory_id,
+                    (start * PAGE_SIZE) as u64,
+                    &bytes[start * PAGE_SIZE..page * PAGE_SIZE],
+                )?;
+            }
+        }
+
+        snapshot.strip_start_function()?;
+
+        Ok(snapshot)
+    }
+
+    fn handle(&mut self, message: Message) {
+        let exactly_once = self.mailbox.as_ref().filter(|mailbox| mailbox.exactly_once);
+        let message_id = match (exactly_once, message.mailbox_key) {
+            (Some(_), Some(_)) => message_id_of(&message),
+            _ => None,
+        };
+        if let (Some(mailbox), Some(id), Some(key)) =
+            (exactly_once, &message_id, message.mailbox_key)
+        {
+            if mailbox.has_processed(id) {
+                println!("{} ... already handled {}", self.name, id);
+                self.acknowledge(key, false, None, Vec::new());
+                return;
+            }
+        }
+
+        self.env.as_mut(&mut self.store).held = message_id.as_ref().map(|_| Vec::new());
+        let handled = self.receive_message(&message.bytes);
+        let held = self.env.as_mut(&mut self.store).held.take();
+        self.report_refused_growths();
+
+        // Whoever gave up on this call has already answered for it
+        if self.cancelled.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Err(error) = &handled {
+            let reason = format!("{:?}", error);
+            reply_with_error(&self.name, &self.outbox, &message, error.code(), &reason);
+        }
+        if let Some(key) = message.mailbox_key {
+            // What trapped mail sent goes the same way as the rest of what it did
+            let held = held.filter(|_| handled.is_ok()).unwrap_or_default();
+            self.acknowledge(key, handled.is_ok(), message_id.as_deref(), held);
+        }
+    }
+
+    /// Saves the instance to the image, so it picks up from here once it's started again.
+    /// Durable instances have already checkpointed everything worth keeping.
+    fn save(&mut self, image: &Mutex<Image>) -> othismo::Result<()> {
+        if self.mailbox.is_some() {
+            return Ok(());
+        }
+
+        let snapshot = self.snapshot()?;
+        image
+            .lock()
+            .unwrap()
+            .replace_object(self.name.as_str(), Object::Instance(snapshot))
+    }
+
+    fn report_refused_growths(&mut self) {
+        let refused: Vec<_> = self.refused.lock().unwrap().drain(..).collect();
+        for growth in refused {
+            let detail = format!(
+                "refused growing {} pages by {}",
+                growth.current.0, growth.requested.0
+            );
+            println!("{} ... {}", self.name, detail);
+            if let Some(events) = &self.events {
+                events.report(event(MEMORY_LIMIT_REACHED, &self.name, &detail));
+            }
+        }
+    }
+
+    /// Checkpoints after handling durable mail, then acknowledges it. Mail that trapped has been
+    /// answered with an error, so it's acknowledged without keeping what it half did. `held`
+    /// messages only go out once they're safely in the image.
+    fn acknowledge(
+        &mut self,
+        key: i64,
+        handled: bool,
+        message_id: Option<&str>,
+        mut held: Vec<Message>,
+    ) {
+        let Some(mailbox) = self.mailbox.clone() else {
+            return;
+        };
+
+        let snapshot = match handled {
+            true => self.snapshot().map(Some),
+            false => Ok(None),
+        };
+        let checkpointed = snapshot.and_then(|snapshot| {
+            mailbox.checkpoint(key, snapshot, message_id, &mut held, &self.cancelled)
+        });
+        match checkpointed {
+            Ok(true) => {
+                for message in held {
+                    self.outbox.send(message).unwrap();
+                }
+            }
+            Ok(false) => println!("{} ... given up on, nothing kept", self.name),
+            Err(error) => println!("{} ... couldn't checkpoint, {:?}", self.name, error),
+        }
+    }
+
+    fn raw_funcref(store: &Store, function: Option<Function>) -> usize {
+        let raw = Value::FuncRef(function).as_raw(store);
+
+        unsafe { raw.funcref }
+    }
+}
+
+/// What the guest thread is asked to do.
+enum Job {
+    /// Handles one message, answering whether it panicked.
+    Handle(Message, oneshot::Sender<std::thread::Result<()>>),
+    /// Saves the instance to the image.
+    Save(Arc<Mutex<Image>>, oneshot::Sender<othismo::Result<()>>),
+    /// Saves the instance to the image and ends the thread, unless it couldn't be saved.
+    Hibernate(Arc<Mutex<Image>>, oneshot::Sender<othismo::Result<()>>),
+}
+
+/// An instance's own thread, where all of its guest code runs: it's loaded there and handed
+/// its mail over a channel, so the async side never runs wasm and instances run in parallel.
+struct GuestThread {
+    jobs: mpsc::Sender<Job>,
+    name: NamespacePath,
+    outbox: Outbox,
+    mailbox: Option<DurableMailbox>,
+    image: Option<Arc<Mutex<Image>>>,
+    hibernate_after: Option<Duration>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl GuestThread {
+    /// Loads the instance on a thread of its own, returning once it's ready for mail. The
+    /// thread ends, and the instance with it, once the `GuestThread` is dropped.
+    async fn start(
+        executor: InstanceExecutor,
+        name: NamespacePath,
+        outbox: Outbox,
+    ) -> othismo::Result<GuestThread> {
+        let (jobs, queued) = mpsc::channel::<Job>();
+        let (started_tx, started_rx) = oneshot::channel();
+        let mailbox = executor.mailbox.clone();
+        let (image, hibernate_after) = (executor.image.clone(), executor.hibernate_after);
+        let (task_name, task_outbox) = (name.clone(), outbox.clone());
+
+        std::thread::Builder::new()
+            .name(format!("guest {}", name))
+            .spawn(move || {
+                let loaded = match executor.load(&task_name, &task_outbox) {
+                    Ok(loaded) => loaded,
+                    Err(error) => {
+                        let _ = started_tx.send(Err(error));
+                        return;
+                    }
+                };
+                let _ = started_tx.send(Ok(loaded.cancelled.clone()));
+                let mut task = executor.into_task(task_name, task_outbox, loaded);
+
+                while let Ok(job) = queued.recv() {
+                    match job {
+                        Job::Handle(message, done) => {
+                            let handled =
+                                panic::catch_unwind(AssertUnwindSafe(|| task.handle(message)));
+                            let panicked = handled.is_err();
+                            let _ = done.send(handled);
+                            if panicked {
+                                return;
+                            }
+                        }
+                        Job::Save(image, done) => {
+                            let _ = done.send(task.save(&image));
+                        }
+                        Job::Hibernate(image, done) => {
+                            let saved = task.save(&image);
+                            let hibernated = saved.is_ok();
+                            let _ = done.send(saved);
+                            if hibernated {
+                                return;
+                            }
+                        }
+                    }
+                }
+            })?;
+
+        let cancelled = started_rx
+            .await
+            .map_err(|_| OthismoError::GuestThreadEnded)??;
+
+        Ok(GuestThread {
+            jobs,
+            name,
+            outbox,
+            mailbox,
+            image,
+            hibernate_after,
+            cancelled,
+        })
+    }
+
+    /// Takes mail from `inbox` one message at a time, waiting on the guest thread to handle
+    /// each. Panics on the guest thread carry on here, for the supervisor to hear of.
+    ///
+    /// A message that isn't handled within `deadline` is answered with `timed_out` and the task
+    /// ends, for its supervisor to restart from what was last saved. wasmer can't stop a call
+    /// from outside it, so the abandoned call carries on until its next call into the host, or
+    /// until it burns through what the deadline meters it with; nothing it does after the
+    /// deadline is kept.
+    ///
+    /// Between messages, instances kept in an image save themselves there whenever they're
+    /// asked through `checkpoints`. Those that hibernate also save themselves once they've gone
+    /// long enough without mail, and hand back `inbox` for the namespace to start them on again.
+    async fn serve(
+        self,
+        mut inbox: Mailbox,
+        deadline: Option<Duration>,
+        checkpoints: &Checkpoints,
+    ) -> Option<Mailbox> {
+        let (requests_tx, mut requests) = unbounded_channel::<CheckpointRequest>();
+        if self.image.is_some() {
+            *checkpoints.lock().unwrap() = Some(requests_tx);
+        }
+        let hibernate_after = self.hibernate_after.filter(|_| self.image.is_some());
+        let mut idle_since = Instant::now();
+
+        loop {
+            let hibernate_at = hibernate_after.map(|after| idle_since + after);
+            let hibernation = async move {
+                match hibernate_at {
+                    Some(at) => tokio::time::sleep_until(at).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let message = tokio::select! {
+                received = inbox.recv() => match received {
+                    Some(message) => message,
+                    None => return None,
+                },
+                Some(done) = requests.recv() => {
+                    if let Some(saved) = self.save(false).await {
+                        let _ = done.send(saved);
+                    }
+                    continue;
+                }
+                _ = hibernation => {
+                    match self.save(true).await {
+                        Some(Ok(())) => {
+                            println!("{} ... hibernating", self.name);
+                            return Some(inbox);
+                        }
+                        Some(Err(error)) => {
+                            println!("{} ... couldn't hibernate, {:?}", self.name, error)
+                        }
+                        None => return None,
+                    }
+                    idle_since = Instant::now();
+                    continue;
+                }
+            };
+            let unanswered = Message {
+                bytes: message.bytes.clone(),
+                sender: message.sender.clone(),
+                mailbox_key: message.mailbox_key,
+                message_id: None,
+                outgoing_key: None,
+            };
+
+            let (done, handling) = oneshot::channel();
+            if self.jobs.send(Job::Handle(message, done)).is_err() {
+                return None;
+            }
+            let handled = match deadline {
+                Some(deadline) => tokio::time::timeout(deadline, handling).await,
+                None => Ok(handling.await),
+            };
+
+            match handled {
+                Ok(Ok(Ok(()))) => {}
+                Ok(Ok(Err(panic))) => panic::resume_unwind(panic),
+                Ok(Err(_)) => return None,
+                Err(_) => {
+                    self.give_up(&unanswered, deadline.unwrap_or_default());
+                    return None;
+                }
+            }
+            idle_since = Instant::now();
+        }
+    }
+
+    /// Asks the guest thread to save the instance, and to end once it has when `hibernating`.
+    /// `None` once the guest thread's gone.
+    async fn save(&self, hibernating: bool) -> Option<othismo::Result<()>> {
+        let image = self.image.clone()?;
+        let (done, saving) = oneshot::channel();
+        let job = match hibernating {
+            true => Job::Hibernate(image, done),
+            false => Job::Save(image, done),
+        };
+        self.jobs.send(job).ok()?;
+
+        saving.await.ok()
+    }
+
+    fn give_up(&self, unanswered: &Message, deadline: Duration) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        if let (Some(mailbox), Some(key)) = (&self.mailbox, unanswered.mailbox_key) {
+            if let Err(error) = mailbox.abandon(key) {
+                println!("{} ... couldn't acknowledge, {:?}", self.name, error);
+            }
+        }
+
+        let reason = format!("not handled within {:?}", deadline);
+        reply_with_error(&self.name, &self.outbox, unanswered, TIMED_OUT, &reason);
+    }
+}
+
+/// Stands in for an instance that couldn't be started, answering everything sent to it with
+/// the reason why.
+pub struct UnstartableTask {
+    ctx: ProcessCtx,
+    code: &'static str,
+    reason: String,
 }
 
-impl Future for InstanceTask {
+impl Future for UnstartableTask {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
         let this = self.get_mut();
 
-        println!("Polling instance");
-        match this.ctx.inbox.poll_recv(cx) {
-            Poll::Ready(Some(message)) => {
-                this.receive_message(&message.bytes).unwrap();
-                Poll::Pending
-            }
-            Poll::Ready(None) => Poll::Ready(()),
-            Poll::Pending => {
-                println!("... pending, no message");
-                Poll::Pending
+        loop {
+            match this.ctx.inbox.poll_recv(cx) {
+                Poll::Ready(Some(message)) => reply_with_error(
+                    this.ctx.name(),
+                    &this.ctx.outbox,
+                    &message,
+                    this.code,
+                    &this.reason,
+                ),
+                Poll::Ready(None) => return Poll::Ready(()),
+                Poll::Pending => return Poll::Pending,
             }
         }
     }
 }
 
+/// Every operator costs the same, so fuel counts roughly how much work the guest did.
+fn operator_cost(_: &Operator) -> u64 {
+    1
+}
+
+fn message_id_of(message: &Message) -> Option<String> {
+    let document = Document::from_reader(message.bytes()).ok()?;
+
+    Some(
+        document
+            .get_document("othismo")
+            .ok()?
+            .get_str("message_id")
+            .ok()?
+            .to_string(),
+    )
+}
+
+/// Answers `message` with an `othismo.error` from `name`, at its `reply_to` or else back to its
+/// sender. Errors themselves are never answered, so two failing processes can't bounce them
+/// forever.
+fn reply_with_error(
+    name: &NamespacePath,
+    outbox: &Outbox,
+    message: &Message,
+    code: &str,
+    reason: &str,
+) {
+    let document = Document::from_reader(message.bytes()).ok();
+    if document.as_ref().is_some_and(is_error) {
+        println!("{} ... {} while handling an error: {}", name, code, reason);
+        return;
+    }
+
+    let recipient = document
+        .as_ref()
+        .and_then(reply_address)
+        .or_else(|| message.sender().cloned());
+
+    match recipient {
+        Some(recipient) => {
+            let mut error = Message::from_document(&error_document(
+                &recipient,
+                document.as_ref(),
+                code,
+                reason,
+            ));
+            error.sender = Some(name.clone());
+            outbox.send(error).unwrap();
+        }
+        None => println!("{} ... {}: {}", name, code, reason),
+    }
+}
+
 mod native_trampolines {
-    use wasmer::{AsStoreMut, FunctionEnvMut};
+    use std::sync::atomic::Ordering;
+    use wasmer::{AsStoreMut, FunctionEnvMut, RuntimeError};
 
     use crate::othismo::Message;
 
     use super::InstanceEnv;
 
-    pub fn send_message(mut env: FunctionEnvMut<InstanceEnv>, head: u32, length: u32) -> u32 {
+    /// Traps a guest that's been given up on as soon as it calls into the host, rather than
+    /// leaving it to run dry.
+    fn check_cancelled(environment: &InstanceEnv) -> Result<(), RuntimeError> {
+        match environment.cancelled.load(Ordering::SeqCst) {
+            true => Err(RuntimeError::new("the call into the guest was given up on")),
+            false => Ok(()),
+        }
+    }
+
+    pub fn send_message(
+        mut env: FunctionEnvMut<InstanceEnv>,
+        head: u32,
+        length: u32,
+    ) -> Result<u32, RuntimeError> {
         let (environment, mut store) = env.data_and_store_mut();
+        check_cancelled(environment)?;
         let view = environment.memory.as_mut().unwrap().view(&store);
+        // The length is the guest's say-so, so it's checked before anything's allocated for it
+        if head as u64 + length as u64 > view.data_size() {
+            return Err(RuntimeError::new("message outside guest memory"));
+        }
         let mut buffer: Vec<u8> = vec![0; length as usize];
-        view.read(head as u64, buffer.as_mut_slice());
+        view.read(head as u64, buffer.as_mut_slice())
+            .map_err(|error| RuntimeError::new(error.to_string()))?;
         let handle = buffer.as_ptr() as u32;
-        environment.outbox.send(Message::new(buffer)).unwrap();
+        let message = Message {
+            bytes: buffer,
+            sender: Some(environment.name.clone()),
+            mailbox_key: None,
+            message_id: None,
+            outgoing_key: None,
+        };
+        environment.send(message);
 
         println!("native::send_message({}, {}) -> {}", head, length, handle);
 
-        return handle;
+        Ok(handle)
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,291 @@
+use crate::othismo::activity::Outbox;
+use crate::othismo::executors::{InstanceExecutor, InstanceTask};
+use crate::othismo::fuel::FuelGauge;
+use crate::othismo::image::{InstanceAtRest, Object};
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::{self, Channel, Errors, Message, OthismoError};
+use bson::doc;
+use std::sync::Arc;
+use std::time::Duration;
+use wasmer::Value;
+
+fn module(wat: &str) -> InstanceAtRest {
+    let wasm = wasmer::wat2wasm(wat.as_bytes()).unwrap().to_vec();
+    match Object::new_module(&wasm).unwrap() {
+        Object::Module(module) => InstanceAtRest::from(module),
+        _ => panic!("expected a module"),
+    }
+}
+
+fn instantiate(wat: &str) -> InstanceTask {
+    restart(module(wat))
+}
+
+fn restart(instance: InstanceAtRest) -> InstanceTask {
+    start(InstanceExecutor::from(instance))
+}
+
+fn start(executor: InstanceExecutor) -> InstanceTask {
+    instantiate_with(executor).unwrap()
+}
+
+/// Loads the instance right here rather than on a guest thread, so tests can reach into it.
+fn instantiate_with(executor: InstanceExecutor) -> othismo::Result<InstanceTask> {
+    let name = NamespacePath::parse("/test/instance").unwrap();
+    let (outbox, _) = Channel::new().split();
+    let outbox = Outbox::new(outbox, Arc::default());
+    let loaded = executor.load(&name, &outbox)?;
+
+    Ok(executor.into_task(name, outbox, loaded))
+}
+
+fn call(task: &mut InstanceTask, name: &str) -> Box<[Value]> {
+    let function = task.instance.exports.get_function(name).unwrap().clone();
+
+    function.call(&mut task.store, &[]).unwrap()
+}
+
+#[test]
+fn snapshots_keep_mutated_tables_and_reference_globals() {
+    let mut task = instantiate(
+        r#"(module
+            (import "env" "dispatch" (table 2 funcref))
+            (import "env" "current" (global $current (mut funcref)))
+            (memory (export "memory") 1)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32))
+            (func $one (result i32) i32.const 1)
+            (func $two (result i32) i32.const 2)
+            (elem declare func $one $two)
+            (func (export "rewire")
+                (table.set 0 (i32.const 0) (ref.func $two))
+                (global.set $current (ref.func $one)))
+            (func (export "dispatch") (result i32)
+                (call_indirect (result i32) (i32.const 0)))
+            (func (export "current_is_set") (result i32)
+                (ref.is_null (global.get $current))
+                i32.eqz))
+        "#,
+    );
+
+    call(&mut task, "rewire");
+    let mut restored = restart(task.snapshot().unwrap());
+
+    assert_eq!(call(&mut restored, "dispatch")[0], Value::I32(2));
+    assert_eq!(call(&mut restored, "current_is_set")[0], Value::I32(1));
+}
+
+#[test]
+fn snapshots_keep_vector_globals() {
+    let mut task = instantiate(
+        r#"(module
+            (import "env" "lanes" (global $lanes (mut v128)))
+            (memory (export "memory") 1)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32))
+            (func (export "fill")
+                (global.set $lanes (v128.const i32x4 1 2 3 4)))
+            (func (export "third_lane") (result i32)
+                (i32x4.extract_lane 2 (global.get $lanes))))
+        "#,
+    );
+
+    call(&mut task, "fill");
+    let mut restored = restart(task.snapshot().unwrap());
+
+    assert_eq!(call(&mut restored, "third_lane")[0], Value::I32(3));
+}
+
+#[test]
+fn snapshots_keep_every_memory() {
+    let mut task = instantiate(
+        r#"(module
+            (import "env" "heap" (memory 1))
+            (memory (export "memory") 1)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32))
+            (func (export "write")
+                (i32.store 1 (i32.const 70000) (i32.const 42))
+                (drop (memory.grow 1 (i32.const 1))))
+            (func (export "read") (result i32)
+                (i32.load 1 (i32.const 70000))))
+        "#,
+    );
+
+    let grown_memory = task.instance.exports.get_memory("memory").unwrap().clone();
+    grown_memory.grow(&mut task.store, 1).unwrap();
+    call(&mut task, "write");
+    let mut restored = restart(task.snapshot().unwrap());
+
+    assert_eq!(call(&mut restored, "read")[0], Value::I32(42));
+}
+
+#[test]
+fn snapshots_of_modules_without_data_keep_memory() {
+    let mut task = instantiate(
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32))
+            (func (export "write") (i32.store (i32.const 16) (i32.const 7)))
+            (func (export "read") (result i32) (i32.load (i32.const 16))))
+        "#,
+    );
+
+    call(&mut task, "write");
+    let mut restored = restart(task.snapshot().unwrap());
+
+    assert_eq!(call(&mut restored, "read")[0], Value::I32(7));
+}
+
+#[test]
+fn snapshots_keep_passive_segments_and_their_dropped_state() {
+    let mut task = instantiate(
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32))
+            (data "kept")
+            (data "gone")
+            (data (i32.const 0) "active")
+            (func (export "drop_gone") (data.drop 1))
+            (func (export "init_kept") (result i32)
+                (memory.init 0 (i32.const 64) (i32.const 0) (i32.const 4))
+                (i32.load8_u (i32.const 64)))
+            (func (export "init_gone")
+                (memory.init 1 (i32.const 64) (i32.const 0) (i32.const 4))))
+        "#,
+    );
+
+    call(&mut task, "drop_gone");
+    let mut restored = restart(task.snapshot().unwrap());
+    let resnapshot = restored.snapshot().unwrap();
+
+    assert_eq!(call(&mut restored, "init_kept")[0], Value::I32(b'k' as i32));
+    let init_gone = restored
+        .instance
+        .exports
+        .get_function("init_gone")
+        .unwrap()
+        .clone();
+    assert!(init_gone.call(&mut restored.store, &[]).is_err());
+    assert_eq!(
+        resnapshot.to_bytes().unwrap().len(),
+        restart(resnapshot)
+            .snapshot()
+            .unwrap()
+            .to_bytes()
+            .unwrap()
+            .len()
+    );
+}
+
+#[test]
+fn memory_limits_cap_growth_and_note_what_was_refused() {
+    let instance = module(
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32))
+            (func (export "grow") (result i32) (memory.grow (i32.const 1))))
+        "#,
+    );
+    let mut task = start(InstanceExecutor::from(instance).with_memory_limit(2));
+
+    assert_eq!(call(&mut task, "grow")[0], Value::I32(1));
+    assert_eq!(call(&mut task, "grow")[0], Value::I32(-1));
+    assert_eq!(task.refused.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn instances_that_start_out_past_their_memory_limit_cannot_start() {
+    let instance = module(
+        r#"(module
+            (memory (export "memory") 4)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32)))
+        "#,
+    );
+
+    assert!(instantiate_with(InstanceExecutor::from(instance).with_memory_limit(2)).is_err());
+}
+
+#[test]
+fn metered_instances_run_out_of_fuel_on_runaway_messages() {
+    let instance = module(
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param $message i32)
+                (local $rounds i32)
+                ;; Spins once for every byte of the message
+                (local.set $rounds (i32.load (local.get $message)))
+                (loop $spin
+                    (local.set $rounds (i32.sub (local.get $rounds) (i32.const 1)))
+                    (br_if $spin (local.get $rounds)))))
+        "#,
+    );
+    let fuel = Arc::new(FuelGauge::new(1_000));
+    let mut task = start(InstanceExecutor::from(instance).with_fuel(fuel.clone()));
+
+    let small = Message::from_document(&doc! {});
+    let large = Message::from_document(&doc! { "padding": "x".repeat(2_000) });
+
+    task.receive_message(small.bytes()).unwrap();
+    assert!(matches!(
+        task.receive_message(large.bytes()),
+        Err(Errors::Othismo(OthismoError::OutOfFuel))
+    ));
+    // Each message gets a fresh budget
+    task.receive_message(small.bytes()).unwrap();
+
+    let usage = fuel.usage();
+    assert_eq!(usage.messages, 3);
+    assert_eq!(usage.exhausted, 1);
+    assert!(usage.last_remaining > 0 && usage.last_remaining < 1_000);
+}
+
+#[test]
+fn deadlines_stop_guests_that_never_call_into_the_host() {
+    let instance = module(
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32) (loop $spin (br $spin))))
+        "#,
+    );
+    let mut task = start(InstanceExecutor::from(instance).with_deadline(Duration::from_millis(10)));
+
+    let message = Message::from_document(&doc! {});
+    assert!(matches!(
+        task.receive_message(message.bytes()),
+        Err(Errors::Othismo(OthismoError::TimedOut))
+    ));
+}
+
+#[test]
+fn what_timed_out_calls_burn_still_counts_against_the_fuel_gauge() {
+    let instance = module(
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32) (loop $spin (br $spin))))
+        "#,
+    );
+    let fuel = Arc::new(FuelGauge::new(u64::MAX));
+    let mut task = start(
+        InstanceExecutor::from(instance)
+            .with_fuel(fuel.clone())
+            .with_deadline(Duration::from_millis(1)),
+    );
+
+    let message = Message::from_document(&doc! {});
+    assert!(matches!(
+        task.receive_message(message.bytes()),
+        Err(Errors::Othismo(OthismoError::TimedOut))
+    ));
+
+    let usage = fuel.usage();
+    assert_eq!((usage.messages, usage.exhausted), (1, 0));
+    assert!(usage.burnt > 0 && usage.last_remaining < u64::MAX);
+}
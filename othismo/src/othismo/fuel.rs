@@ -0,0 +1,65 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How much fuel an instance may burn handling each message, and what it's burnt so far.
+/// Every wasm operator costs one unit; running out traps the guest.
+#[derive(Debug)]
+pub struct FuelGauge {
+    budget: u64,
+    messages: AtomicU64,
+    burnt: AtomicU64,
+    last_remaining: AtomicU64,
+    exhausted: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FuelUsage {
+    pub budget: u64,
+    pub messages: u64,
+    pub burnt: u64,
+    /// What was left after the last message, zero when it ran out.
+    pub last_remaining: u64,
+    pub exhausted: u64,
+}
+
+impl FuelGauge {
+    pub fn new(budget: u64) -> FuelGauge {
+        FuelGauge {
+            budget,
+            messages: AtomicU64::new(0),
+            burnt: AtomicU64::new(0),
+            last_remaining: AtomicU64::new(budget),
+            exhausted: AtomicU64::new(0),
+        }
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// Records a message that was handled with `remaining` fuel to spare.
+    pub fn record(&self, remaining: u64) {
+        self.messages.fetch_add(1, Ordering::SeqCst);
+        self.burnt
+            .fetch_add(self.budget.saturating_sub(remaining), Ordering::SeqCst);
+        self.last_remaining.store(remaining, Ordering::SeqCst);
+    }
+
+    /// Records a message that burnt through the whole budget.
+    pub fn record_exhausted(&self) {
+        self.record(0);
+        self.exhausted.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn usage(&self) -> FuelUsage {
+        FuelUsage {
+            budget: self.budget,
+            messages: self.messages.load(Ordering::SeqCst),
+            burnt: self.burnt.load(Ordering::SeqCst),
+            last_remaining: self.last_remaining.load(Ordering::SeqCst),
+            exhausted: self.exhausted.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,33 @@
+use crate::othismo::fuel::{FuelGauge, FuelUsage};
+
+#[test]
+fn gauges_add_up_what_each_message_burnt() {
+    let gauge = FuelGauge::new(100);
+
+    gauge.record(70);
+    gauge.record(90);
+
+    assert_eq!(
+        gauge.usage(),
+        FuelUsage {
+            budget: 100,
+            messages: 2,
+            burnt: 40,
+            last_remaining: 90,
+            exhausted: 0,
+        }
+    );
+}
+
+#[test]
+fn running_out_burns_the_whole_budget() {
+    let gauge = FuelGauge::new(100);
+
+    gauge.record(60);
+    gauge.record_exhausted();
+
+    let usage = gauge.usage();
+    assert_eq!(usage.burnt, 140);
+    assert_eq!(usage.last_remaining, 0);
+    assert_eq!(usage.exhausted, 1);
+}
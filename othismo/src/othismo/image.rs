@@ -1,30 +1,35 @@
 use super::OthismoError;
+use crate::othismo::namespace_path::NamespacePath;
 use crate::othismo::OthismoError::{
-    ImageAlreadyExists, ObjectAlreadyExists, ObjectDoesNotExist, ObjectNotFree,
+    ImageAlreadyExists, ImageInUse, ObjectAlreadyExists, ObjectDoesNotExist, ObjectNotFree,
 };
 use crate::othismo::{Errors, Result};
 use bson::Document;
 use core::panic;
-use rusqlite::{params, Connection, OptionalExtension};
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension};
 use serde::{Deserialize, Serialize};
 use std::any::Any;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::format;
 use std::io::BufWriter;
 use std::path::{Path, PathBuf};
 use wasmbin::builtins::{Blob, FloatConst, Lazy, UnparsedBytes};
-use wasmbin::indices::{GlobalId, MemId, TypeId};
-use wasmbin::instructions::Instruction;
+use wasmbin::indices::{DataId, FuncId, GlobalId, MemId, TableId, TypeId};
+use wasmbin::instructions::{Instruction, Misc, SIMD};
 use wasmbin::io::Decode;
 use wasmbin::sections::Section::Start;
 use wasmbin::sections::{
-    payload, CustomSection, Data, DataInit, Export, ExportDesc, Global, Import, ImportDesc, Kind,
-    RawCustomSection, Section,
+    payload, CustomSection, Data, DataInit, ElemKind, Element, Export, ExportDesc, Global, Import,
+    ImportDesc, Kind, RawCustomSection, Section,
 };
-use wasmbin::types::{Limits, MemType};
+use wasmbin::types::{Limits, MemType, RefType, TableType, ValueType};
 use wasmbin::Module;
 use wasmer::{GlobalType, Store, Type};
 
+const DATA_DROPPED_GLOBAL_PREFIX: &str = "othismo_data_dropped_";
+const SOURCE_SECTION: &str = "othismo.source";
+const WASM_PAGE_SIZE: u64 = 65536;
+
 #[derive(Clone)]
 pub struct InstanceAtRest(wasmbin::Module);
 pub struct ModuleAtRest(wasmbin::Module);
@@ -35,28 +40,12 @@ pub enum Object {
 }
 
 impl InstanceAtRest {
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
-        self.0.encode_into(BufWriter::new(&mut buffer));
-
-        buffer
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        encode_module(&self.0)
     }
 
     pub fn set_exported_global(&mut self, name: &str, value: wasmer::Value) -> Result<()> {
-        let global_index = {
-            let export = self
-                .0
-                .find_or_insert_std_section(|| payload::Export::default())
-                .try_contents_mut()?
-                .iter_mut()
-                .find(|e| e.name == name)
-                .expect("we should never set an non-extant export");
-
-            match export.desc {
-                wasmbin::sections::ExportDesc::Global(index) => index.index as usize,
-                _ => unimplemented!("Only global exports supported"),
-            }
-        };
+        let global_index = self.exported_global_index(name)?;
 
         let global = self
             .0
@@ -69,7 +58,7 @@ impl InstanceAtRest {
             wasmbin::types::ValueType::F64 => {
                 let float = match value {
                     wasmer::Value::F64(f) => f,
-                    _ => panic!(),
+                    _ => return Err(OthismoError::GlobalTypeMismatch(name.to_string()).into()),
                 };
 
                 global.init = vec![Instruction::F64Const(FloatConst { value: float })]
@@ -77,7 +66,7 @@ impl InstanceAtRest {
             wasmbin::types::ValueType::F32 => {
                 let float = match value {
                     wasmer::Value::F32(f) => f,
-                    _ => panic!(),
+                    _ => return Err(OthismoError::GlobalTypeMismatch(name.to_string()).into()),
                 };
 
                 global.init = vec![Instruction::F32Const(FloatConst { value: float })]
@@ -85,7 +74,7 @@ impl InstanceAtRest {
             wasmbin::types::ValueType::I64 => {
                 let int = match value {
                     wasmer::Value::I64(i) => i,
-                    _ => panic!(),
+                    _ => return Err(OthismoError::GlobalTypeMismatch(name.to_string()).into()),
                 };
 
                 global.init = vec![Instruction::I64Const(int)]
@@ -93,50 +82,185 @@ impl InstanceAtRest {
             wasmbin::types::ValueType::I32 => {
                 let int = match value {
                     wasmer::Value::I32(i) => i,
-                    _ => panic!(),
+                    _ => return Err(OthismoError::GlobalTypeMismatch(name.to_string()).into()),
                 };
 
                 global.init = vec![Instruction::I32Const(int)]
             }
-            _ => unimplemented!("Only global int & float exports supported"),
+            wasmbin::types::ValueType::V128 => {
+                let vector = match value {
+                    wasmer::Value::V128(v) => v,
+                    _ => return Err(OthismoError::GlobalTypeMismatch(name.to_string()).into()),
+                };
+
+                global.init = vec![Instruction::SIMD(SIMD::V128Const(vector.to_le_bytes()))]
+            }
+            // Reference globals are set via set_exported_reference_global
+            wasmbin::types::ValueType::Ref(_) => {
+                return Err(OthismoError::GlobalTypeMismatch(name.to_string()).into())
+            }
         };
 
         Ok(())
     }
 
-    pub fn clear_data_segments(&mut self) -> Result<()> {
-        if let Some(data_count_section) = self.0.find_std_section_mut::<payload::DataCount>() {
-            if let Ok(data_count) = data_count_section.try_contents_mut() {
-                *data_count = 0;
-            }
+    /// Host references can't outlive the instance, so anything other than a function we know
+    /// the index of is persisted as `ref.null`.
+    pub fn set_exported_reference_global(
+        &mut self,
+        name: &str,
+        function: Option<FuncId>,
+    ) -> Result<()> {
+        let global_index = self.exported_global_index(name)?;
+
+        let global = self
+            .0
+            .find_or_insert_std_section(payload::Global::default)
+            .try_contents_mut()?
+            .get_mut(global_index)
+            .unwrap();
+
+        global.init = match (&global.ty.value_type, function) {
+            (ValueType::Ref(RefType::Func), Some(function)) => vec![Instruction::RefFunc(function)],
+            (ValueType::Ref(ref_type), _) => vec![Instruction::RefNull(ref_type.clone())],
+            _ => return Err(OthismoError::GlobalTypeMismatch(name.to_string()).into()),
+        };
+
+        Ok(())
+    }
+
+    fn exported_global_index(&mut self, name: &str) -> Result<usize> {
+        let export = self
+            .0
+            .find_or_insert_std_section(|| payload::Export::default())
+            .try_contents_mut()?
+            .iter_mut()
+            .find(|e| e.name == name)
+            .expect("we should never set an non-extant export");
+
+        match export.desc {
+            wasmbin::sections::ExportDesc::Global(index) => Ok(index.index as usize),
+            _ => unimplemented!("Only global exports supported"),
         }
+    }
+
+    /// Active segments have done their job by the time a snapshot is taken, but their indices
+    /// are still what `memory.init` & `data.drop` refer to, so they're kept as empty passive
+    /// segments.  Passive segments survive, unless the `othismo_data_dropped_N` global (already
+    /// snapshotted) says the instance dropped them.
+    pub fn clear_data_segments(&mut self) -> Result<()> {
+        let dropped = self.dropped_data_segments()?;
+        let referenced = self.referenced_data_segments()?;
 
         if let Some(data_section) = self.0.find_std_section_mut::<payload::Data>() {
             let data_segments = data_section.try_contents_mut()?;
-            data_segments.clear();
+            for (index, segment) in data_segments.iter_mut().enumerate() {
+                match segment.init {
+                    DataInit::Passive if !dropped.contains(&(index as u32)) => {}
+                    _ => {
+                        segment.init = DataInit::Passive;
+                        segment.blob.clear();
+                    }
+                }
+            }
+
+            // Segments left behind by earlier snapshots are never referenced by code
+            while data_segments.len() > referenced
+                && data_segments
+                    .last()
+                    .is_some_and(|segment| segment.blob.is_empty())
+            {
+                data_segments.pop();
+            }
         }
 
+        self.sync_data_count()
+    }
+
+    pub fn add_data_segment(&mut self, memory: MemId, offset: u64, bytes: &[u8]) -> Result<()> {
+        let offset = u32::try_from(offset)
+            .map_err(|_| OthismoError::MemoryTooLarge(offset / WASM_PAGE_SIZE))?;
+        // `i32.const` offsets are read back as unsigned
+        let offset = vec![Instruction::I32Const(offset as i32)];
+
+        let data_segments = self
+            .0
+            .find_or_insert_std_section(payload::Data::default)
+            .try_contents_mut()?;
+
+        data_segments.push(Data {
+            init: match memory.index {
+                0 => DataInit::Active { offset },
+                _ => DataInit::ActiveWithMemory { memory, offset },
+            },
+            blob: bytes.into(),
+        });
+
+        self.sync_data_count()
+    }
+
+    fn sync_data_count(&mut self) -> Result<()> {
+        let data_count = match self.0.find_std_section::<payload::Data>() {
+            Some(data_section) => data_section.try_contents()?.len() as u32,
+            None => return Ok(()),
+        };
+
+        *self
+            .0
+            .find_or_insert_std_section::<payload::DataCount>(|| 0)
+            .try_contents_mut()? = data_count;
+
         Ok(())
     }
 
-    pub fn add_data_segment(&mut self, offset: i32, bytes: &[u8]) -> Result<()> {
-        if let Some(data_count_section) = self.0.find_std_section_mut::<payload::DataCount>() {
-            if let Ok(data_count) = data_count_section.try_contents_mut() {
-                *data_count += 1;
+    /// How many leading data segments code may refer to via `memory.init` or `data.drop`.
+    fn referenced_data_segments(&self) -> Result<usize> {
+        let mut referenced = 0;
+
+        if let Some(code_section) = self.0.find_std_section::<payload::Code>() {
+            for function in code_section.try_contents()? {
+                for instruction in &function.try_contents()?.expr {
+                    if let Instruction::Misc(Misc::MemoryInit { data, .. } | Misc::DataDrop(data)) =
+                        instruction
+                    {
+                        referenced = std::cmp::max(referenced, data.index as usize + 1);
+                    }
+                }
             }
         }
 
-        if let Some(data_section) = self.0.find_std_section_mut::<payload::Data>() {
-            let data_segments = data_section.try_contents_mut()?;
-            data_segments.push(Data {
-                init: DataInit::Active {
-                    offset: vec![Instruction::I32Const(offset)],
-                },
-                blob: bytes.into(),
-            });
+        Ok(referenced)
+    }
+
+    fn dropped_data_segments(&self) -> Result<Vec<u32>> {
+        let mut dropped = Vec::new();
+
+        let (Some(export_section), Some(global_section)) = (
+            self.0.find_std_section::<payload::Export>(),
+            self.0.find_std_section::<payload::Global>(),
+        ) else {
+            return Ok(dropped);
+        };
+        let globals = global_section.try_contents()?;
+
+        for export in export_section.try_contents()? {
+            let (Some(data_index), ExportDesc::Global(global)) = (
+                export.name.strip_prefix(DATA_DROPPED_GLOBAL_PREFIX),
+                &export.desc,
+            ) else {
+                continue;
+            };
+
+            let is_dropped = globals
+                .get(global.index as usize)
+                .is_some_and(|global| global.init == vec![Instruction::I32Const(1)]);
+
+            if let (true, Ok(data_index)) = (is_dropped, data_index.parse()) {
+                dropped.push(data_index);
+            }
         }
 
-        Ok(())
+        Ok(dropped)
     }
 
     pub fn strip_start_function(&mut self) -> Result<()> {
@@ -159,36 +283,177 @@ impl InstanceAtRest {
         Ok(())
     }
 
-    pub fn resize_memory(&mut self, target_bytes: u64) -> Result<()> {
-        let memories = self
+    pub fn resize_memory(&mut self, memory: MemId, target_bytes: u64) -> Result<()> {
+        let memory = self
             .0
             .find_or_insert_std_section(|| payload::Memory::default())
-            .try_contents_mut()?;
+            .try_contents_mut()?
+            .get_mut(memory.index as usize)
+            .ok_or(OthismoError::NoSuchMemory(memory.index))?;
+
+        let pages = target_bytes / WASM_PAGE_SIZE;
+        let pages = u32::try_from(pages).map_err(|_| OthismoError::MemoryTooLarge(pages))?;
+        memory.limits.min = std::cmp::max(memory.limits.min, pages);
+
+        Ok(())
+    }
+
+    /// Every memory is exported once imports are rewritten, so this covers all of them.
+    /// A memory exported under several names is only listed once.
+    pub fn exported_memories(&self) -> Result<Vec<(MemId, String)>> {
+        let mut memories: Vec<(MemId, String)> = Vec::new();
+
+        if let Some(export_section) = self.0.find_std_section::<payload::Export>() {
+            for export in export_section.try_contents()? {
+                if let ExportDesc::Mem(memory) = export.desc {
+                    if !memories.iter().any(|(known, _)| *known == memory) {
+                        memories.push((memory, export.name.clone()));
+                    }
+                }
+            }
+        }
+
+        Ok(memories)
+    }
+
+    pub fn exported_tables(&self) -> Result<Vec<(TableId, String)>> {
+        let mut tables: Vec<(TableId, String)> = Vec::new();
+
+        if let Some(export_section) = self.0.find_std_section::<payload::Export>() {
+            for export in export_section.try_contents()? {
+                if let ExportDesc::Table(table) = export.desc {
+                    if !tables.iter().any(|(known, _)| *known == table) {
+                        tables.push((table, export.name.clone()));
+                    }
+                }
+            }
+        }
 
-        assert!(memories.len() <= 1);
-        for memory in memories {
-            memory.limits.min = std::cmp::max(memory.limits.min, (target_bytes / 65536) as u32);
+        Ok(tables)
+    }
+
+    pub fn exported_functions(&self) -> Result<Vec<(FuncId, String)>> {
+        let mut functions = Vec::new();
+
+        if let Some(export_section) = self.0.find_std_section::<payload::Export>() {
+            for export in export_section.try_contents()? {
+                if let ExportDesc::Func(function) = export.desc {
+                    functions.push((function, export.name.clone()));
+                }
+            }
+        }
+
+        Ok(functions)
+    }
+
+    pub fn table_type(&self, table: TableId) -> Result<TableType> {
+        let table_type = self
+            .0
+            .find_std_section::<payload::Table>()
+            .expect("we should never look up a non-extant table")
+            .try_contents()?
+            .get(table.index as usize)
+            .expect("we should never look up a non-extant table")
+            .clone();
+
+        Ok(table_type)
+    }
+
+    pub fn resize_table(&mut self, table: TableId, target_elements: u32) -> Result<()> {
+        let table = self
+            .0
+            .find_or_insert_std_section(payload::Table::default)
+            .try_contents_mut()?
+            .get_mut(table.index as usize)
+            .expect("we should never resize a non-extant table");
+
+        table.limits.min = std::cmp::max(table.limits.min, target_elements);
+
+        Ok(())
+    }
+
+    /// Active segments would overwrite the restored table contents during instantiation, so they
+    /// become declarative ones.  That keeps element indices stable and the functions they name
+    /// valid targets for `ref.func`.
+    pub fn clear_element_segments(&mut self) -> Result<()> {
+        if let Some(element_section) = self.0.find_std_section_mut::<payload::Element>() {
+            for element in element_section.try_contents_mut()? {
+                let declarative = match element {
+                    Element::ActiveWithFuncs { funcs, .. } => Element::DeclarativeWithFuncs {
+                        kind: ElemKind::FuncRef,
+                        funcs: std::mem::take(funcs),
+                    },
+                    Element::ActiveWithTableAndFuncs { kind, funcs, .. } => {
+                        Element::DeclarativeWithFuncs {
+                            kind: kind.clone(),
+                            funcs: std::mem::take(funcs),
+                        }
+                    }
+                    Element::ActiveWithExprs { exprs, .. } => Element::DeclarativeWithExprs {
+                        ty: RefType::Func,
+                        exprs: std::mem::take(exprs),
+                    },
+                    Element::ActiveWithTableAndExprs { ty, exprs, .. } => {
+                        Element::DeclarativeWithExprs {
+                            ty: ty.clone(),
+                            exprs: std::mem::take(exprs),
+                        }
+                    }
+                    _ => continue,
+                };
+
+                *element = declarative;
+            }
         }
 
         Ok(())
     }
+
+    pub fn add_element_segment(
+        &mut self,
+        table: TableId,
+        offset: i32,
+        functions: &[Option<FuncId>],
+    ) -> Result<()> {
+        let exprs = functions
+            .iter()
+            .map(|function| match function {
+                Some(function) => vec![Instruction::RefFunc(*function)],
+                None => vec![Instruction::RefNull(RefType::Func)],
+            })
+            .collect();
+
+        self.0
+            .find_or_insert_std_section(payload::Element::default)
+            .try_contents_mut()?
+            .push(Element::ActiveWithTableAndExprs {
+                table,
+                offset: vec![Instruction::I32Const(offset)],
+                ty: RefType::Func,
+                exprs,
+            });
+
+        Ok(())
+    }
 }
 
 impl ModuleAtRest {
     pub fn import(mut module: wasmbin::Module) -> Result<Self> {
         module = ModuleAtRest::export_all_globals(module)?;
         let limits = ModuleAtRest::remove_memory_imports(&mut module)?;
-        ModuleAtRest::add_memory_segments(&mut module, &limits);
-        ModuleAtRest::add_memory_exports(&mut module, limits.len())?;
+        ModuleAtRest::add_memory_segments(&mut module, &limits)?;
+        ModuleAtRest::add_memory_exports(&mut module)?;
+        let table_types = ModuleAtRest::remove_table_imports(&mut module)?;
+        ModuleAtRest::add_tables(&mut module, &table_types)?;
+        ModuleAtRest::add_table_exports(&mut module)?;
+        ModuleAtRest::add_referenced_function_exports(&mut module)?;
+        ModuleAtRest::track_dropped_data_segments(&mut module)?;
 
         Ok(ModuleAtRest(module))
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
-        self.0.encode_into(BufWriter::new(&mut buffer));
-
-        buffer
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        encode_module(&self.0)
     }
 
     fn remove_memory_imports(module: &mut wasmbin::Module) -> Result<Vec<Limits>, Errors> {
@@ -213,28 +478,29 @@ impl ModuleAtRest {
 
     fn add_memory_segments(
         module: &mut wasmbin::Module,
-        limits: &Vec<Limits>,
+        limits: &[Limits],
     ) -> Result<usize, Errors> {
         if (limits.len() == 0) {
             return Ok(0);
         }
 
+        // Imported memories come first in the index space, so they go ahead of the defined ones
         let mut memories = module
             .find_or_insert_std_section(|| payload::Memory::default())
             .try_contents_mut()?;
-        for limit in limits {
-            memories.push(MemType {
-                limits: limit.clone(),
-            });
+        for (index, limit) in limits.iter().enumerate() {
+            memories.insert(
+                index,
+                MemType {
+                    limits: limit.clone(),
+                },
+            );
         }
 
         return Ok(limits.len());
     }
 
-    fn add_memory_exports(
-        module: &mut wasmbin::Module,
-        imports_to_replace: usize,
-    ) -> Result<usize, Errors> {
+    fn add_memory_exports(module: &mut wasmbin::Module) -> Result<usize, Errors> {
         let memory_count = {
             if let Some(memory_section) = module.find_std_section::<payload::Memory>() {
                 memory_section.contents.try_contents()?.len()
@@ -244,28 +510,215 @@ impl ModuleAtRest {
         };
 
         let exports = module
-            .find_or_insert_std_section(|| payload::Export::default())
+            .find_or_insert_std_section(payload::Export::default)
             .try_contents_mut()?;
-        let exports_already_existing = exports
-            .iter()
-            .map(|e| &e.desc)
-            .filter(|e| matches!(e, ExportDesc::Mem(_)))
-            .count();
 
-        assert!(
-            memory_count <= 1,
-            "WASM only supports up to 1 memory, for now"
-        );
+        let missing: Vec<u32> = (0..memory_count as u32)
+            .filter(|&index| {
+                !exports
+                    .iter()
+                    .any(|e| matches!(e.desc, ExportDesc::Mem(MemId { index: ei }) if ei == index))
+            })
+            .collect();
+
+        for index in &missing {
+            exports.push(Export {
+                desc: ExportDesc::Mem(MemId { index: *index }),
+                name: format!("othismo_memory_{}", index),
+            });
+        }
+
+        Ok(missing.len())
+    }
+
+    fn remove_table_imports(module: &mut wasmbin::Module) -> Result<Vec<TableType>, Errors> {
+        let mut table_types = Vec::new();
+
+        let imports = module
+            .find_or_insert_std_section(payload::Import::default)
+            .try_contents_mut()?;
+
+        let mut index = 0;
+        while index < imports.len() {
+            if let ImportDesc::Table(table_type) = &imports[index].desc {
+                table_types.push(table_type.clone());
+                imports.remove(index);
+            } else {
+                index += 1;
+            }
+        }
+
+        Ok(table_types)
+    }
+
+    fn add_tables(module: &mut wasmbin::Module, table_types: &[TableType]) -> Result<(), Errors> {
+        if table_types.is_empty() {
+            return Ok(());
+        }
+
+        // Imported tables come first in the index space, so they go ahead of the defined ones
+        let tables = module
+            .find_or_insert_std_section(payload::Table::default)
+            .try_contents_mut()?;
+        for (index, table_type) in table_types.iter().enumerate() {
+            tables.insert(index, table_type.clone());
+        }
+
+        Ok(())
+    }
+
+    fn add_table_exports(module: &mut wasmbin::Module) -> Result<usize, Errors> {
+        let table_count = match module.find_std_section::<payload::Table>() {
+            Some(table_section) => table_section.contents.try_contents()?.len(),
+            None => 0,
+        };
+
+        let exports = module
+            .find_or_insert_std_section(payload::Export::default)
+            .try_contents_mut()?;
 
-        if imports_to_replace > 0 || (memory_count - exports_already_existing) > 0 {
+        let missing: Vec<u32> = (0..table_count as u32)
+            .filter(|&index| {
+                !exports.iter().any(
+                    |e| matches!(e.desc, ExportDesc::Table(TableId { index: ei }) if ei == index),
+                )
+            })
+            .collect();
+
+        for index in &missing {
             exports.push(Export {
-                desc: ExportDesc::Mem(MemId { index: 0 }),
-                name: format!("othismo_memory_{}", 0),
+                desc: ExportDesc::Table(TableId { index: *index }),
+                name: format!("othismo_table_{}", index),
             });
-            Ok(1)
-        } else {
-            Ok(0)
         }
+
+        Ok(missing.len())
+    }
+
+    /// A function can only end up in a table if an element segment declares it, so exporting
+    /// those is enough to map every table entry back to its index when snapshotting.
+    fn add_referenced_function_exports(module: &mut wasmbin::Module) -> Result<usize, Errors> {
+        let mut referenced: Vec<FuncId> = Vec::new();
+
+        if let Some(element_section) = module.find_std_section::<payload::Element>() {
+            for element in element_section.try_contents()? {
+                match element {
+                    Element::ActiveWithFuncs { funcs, .. }
+                    | Element::PassiveWithFuncs { funcs, .. }
+                    | Element::ActiveWithTableAndFuncs { funcs, .. }
+                    | Element::DeclarativeWithFuncs { funcs, .. } => {
+                        referenced.extend(funcs.iter().copied())
+                    }
+                    Element::ActiveWithExprs { exprs, .. }
+                    | Element::PassiveWithExprs { exprs, .. }
+                    | Element::ActiveWithTableAndExprs { exprs, .. }
+                    | Element::DeclarativeWithExprs { exprs, .. } => {
+                        for expr in exprs {
+                            for instruction in expr {
+                                if let Instruction::RefFunc(function) = instruction {
+                                    referenced.push(*function);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let exports = module
+            .find_or_insert_std_section(payload::Export::default)
+            .try_contents_mut()?;
+
+        let mut added = 0;
+        for function in referenced {
+            let already_exported = exports
+                .iter()
+                .any(|e| matches!(e.desc, ExportDesc::Func(id) if id == function));
+
+            if !already_exported {
+                exports.push(Export {
+                    desc: ExportDesc::Func(function),
+                    name: format!("othismo_func_{}", function.index),
+                });
+                added += 1;
+            }
+        }
+
+        Ok(added)
+    }
+
+    /// Whether a passive segment was dropped isn't observable from the host, so every
+    /// `data.drop` of one also raises an exported `othismo_data_dropped_N` flag.
+    fn track_dropped_data_segments(module: &mut wasmbin::Module) -> Result<usize, Errors> {
+        let passive: Vec<u32> = match module.find_std_section::<payload::Data>() {
+            Some(data_section) => data_section
+                .try_contents()?
+                .iter()
+                .enumerate()
+                .filter(|(_, segment)| matches!(segment.init, DataInit::Passive))
+                .map(|(index, _)| index as u32)
+                .collect(),
+            None => Vec::new(),
+        };
+
+        if passive.is_empty() {
+            return Ok(0);
+        }
+
+        let globals = module
+            .find_or_insert_std_section(payload::Global::default)
+            .try_contents_mut()?;
+        let first_flag = globals.len() as u32;
+        for _ in &passive {
+            globals.push(Global {
+                ty: wasmbin::types::GlobalType {
+                    value_type: ValueType::I32,
+                    mutable: true,
+                },
+                init: vec![Instruction::I32Const(0)],
+            });
+        }
+        let flag_of = |data: DataId| {
+            passive
+                .iter()
+                .position(|index| *index == data.index)
+                .map(|position| GlobalId {
+                    index: first_flag + position as u32,
+                })
+        };
+
+        if let Some(code_section) = module.find_std_section_mut::<payload::Code>() {
+            for function in code_section.try_contents_mut()? {
+                let body = function.try_contents_mut()?;
+                let mut index = 0;
+                while index < body.expr.len() {
+                    if let Instruction::Misc(Misc::DataDrop(data)) = body.expr[index] {
+                        if let Some(flag) = flag_of(data) {
+                            body.expr.splice(
+                                index..index,
+                                [Instruction::I32Const(1), Instruction::GlobalSet(flag)],
+                            );
+                            index += 2;
+                        }
+                    }
+                    index += 1;
+                }
+            }
+        }
+
+        let exports = module
+            .find_or_insert_std_section(payload::Export::default)
+            .try_contents_mut()?;
+        for (position, data_index) in passive.iter().enumerate() {
+            exports.push(Export {
+                name: format!("{}{}", DATA_DROPPED_GLOBAL_PREFIX, data_index),
+                desc: ExportDesc::Global(GlobalId {
+                    index: first_flag + position as u32,
+                }),
+            });
+        }
+
+        Ok(passive.len())
     }
 
     fn export_all_globals(mut module: wasmbin::Module) -> Result<wasmbin::Module, Errors> {
@@ -365,14 +818,12 @@ impl ModuleAtRest {
                         }
                         wasmbin::types::ValueType::I64 => vec![Instruction::I64Const(0)],
                         wasmbin::types::ValueType::I32 => vec![Instruction::I32Const(0)],
-                        wasmbin::types::ValueType::Ref(_) => {
-                            Err(OthismoError::UnsupportedModuleDefinition(
-                                "reference_type_global".to_string(),
-                            ))?
+                        wasmbin::types::ValueType::Ref(ref_type) => {
+                            vec![Instruction::RefNull(ref_type.clone())]
+                        }
+                        wasmbin::types::ValueType::V128 => {
+                            vec![Instruction::SIMD(SIMD::V128Const([0; 16]))]
                         }
-                        wasmbin::types::ValueType::V128 => Err(
-                            OthismoError::UnsupportedModuleDefinition("simd_global".to_string()),
-                        )?,
                     },
                 },
                 _ => panic!(),
@@ -385,6 +836,47 @@ impl ModuleAtRest {
     }
 }
 
+fn encode_module(module: &wasmbin::Module) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    module.encode_into(&mut buffer)?;
+
+    Ok(buffer)
+}
+
+fn find_custom_section<'a>(module: &'a wasmbin::Module, name: &str) -> Result<Option<&'a [u8]>> {
+    for section in &module.sections {
+        if let Some(custom_section) = section.try_as::<payload::Custom>() {
+            if let CustomSection::Other(raw) = custom_section.try_contents()? {
+                if raw.name == name {
+                    return Ok(Some(&raw.data[..]));
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+fn set_custom_section(module: &mut wasmbin::Module, name: &str, data: Vec<u8>) -> Result<()> {
+    let section = CustomSection::Other(RawCustomSection {
+        name: name.to_string(),
+        data: data.into(),
+    });
+
+    for existing in module.sections.iter_mut() {
+        if let Some(custom_section) = existing.try_as_mut::<payload::Custom>() {
+            if custom_section.try_contents()?.name() == name {
+                *custom_section = section.into();
+                return Ok(());
+            }
+        }
+    }
+
+    module.sections.push(Section::Custom(section.into()));
+
+    Ok(())
+}
+
 impl From<wasmbin::Module> for InstanceAtRest {
     fn from(value: wasmbin::Module) -> Self {
         InstanceAtRest(value)
@@ -411,7 +903,7 @@ impl Object {
         }
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
         fn to_vec(module: &wasmbin::Module) -> Vec<u8> {
             let mut buffer = Vec::new();
             module.encode_into(BufWriter::new(&mut buffer));
@@ -438,11 +930,33 @@ impl Object {
     }
 
     pub fn new_module(bytes: &Vec<u8>) -> Result<Object> {
+        abi::validate_module(bytes)?;
+        let mut module = Module::decode_from(bytes.as_slice())?;
+
+        Ok(Object::Module(ModuleAtRest::import(module)?))
+    }
+
+    /// Compiles WAT text into a module, keeping the text in the `othismo.source` custom section
+    /// so that the module & any instances made from it can show where they came from.
+    pub fn new_module_from_wat(text: &str) -> Result<Object> {
+        let bytes = wat::parse_str(text)?;
+        abi::validate_module(&bytes)?;
         let mut module = Module::decode_from(bytes.as_slice())?;
+        set_custom_section(&mut module, SOURCE_SECTION, text.as_bytes().to_vec())?;
 
         Ok(Object::Module(ModuleAtRest::import(module)?))
     }
 
+    pub fn source(&self) -> Result<Option<String>> {
+        let module = match self {
+            Object::Module(module) => &module.0,
+            Object::Instance(instance) => &instance.0,
+        };
+
+        Ok(find_custom_section(module, SOURCE_SECTION)?
+            .map(|source| String::from_utf8_lossy(source).into_owned()))
+    }
+
     pub fn new_instance(object: &Object) -> Result<Object> {
         let module = match object {
             Object::Module(inner_module) => inner_module,
@@ -469,6 +983,39 @@ pub struct Image {
     file: Connection,
 }
 
+enum Migration {
+    Script(&'static str),
+    Rewrite(fn(&mut Image) -> Result<()>),
+}
+
+// Applied in order on top of `create_image_schema.sql`; `PRAGMA user_version` records how many
+// of these an image has already seen.
+const MIGRATIONS: &[Migration] = &[
+    Migration::Script(include_str!(
+        "../sql_scripts/migrate_001_object_revisions.sql"
+    )),
+    Migration::Rewrite(Image::normalize_namespace_paths),
+    Migration::Script(include_str!("../sql_scripts/migrate_003_access_rules.sql")),
+    Migration::Script(include_str!("../sql_scripts/migrate_004_capabilities.sql")),
+    Migration::Script(include_str!("../sql_scripts/migrate_005_supervision.sql")),
+    Migration::Script(include_str!("../sql_scripts/migrate_006_dead_letters.sql")),
+    Migration::Script(include_str!(
+        "../sql_scripts/migrate_007_durable_mailboxes.sql"
+    )),
+    Migration::Script(include_str!("../sql_scripts/migrate_008_exactly_once.sql")),
+    Migration::Script(include_str!(
+        "../sql_scripts/migrate_009_mailbox_limits.sql"
+    )),
+    Migration::Script(include_str!("../sql_scripts/migrate_010_fuel_budgets.sql")),
+    Migration::Script(include_str!(
+        "../sql_scripts/migrate_011_execution_deadlines.sql"
+    )),
+    Migration::Script(include_str!("../sql_scripts/migrate_012_memory_limits.sql")),
+    Migration::Script(include_str!(
+        "../sql_scripts/migrate_013_hibernation_periods.sql"
+    )),
+];
+
 impl Image {
     pub fn create<P: AsRef<Path>>(path: P) -> Result<Image> {
         if let Ok(_) = std::fs::metadata(&path) {
@@ -479,48 +1026,161 @@ impl Image {
 
         connection.execute_batch(include_str!("../sql_scripts/create_image_schema.sql"))?;
 
-        Ok(Image {
+        let mut image = Image {
             path_name: path.as_ref().to_path_buf(),
             file: connection,
-        })
+        };
+        image.migrate()?;
+
+        Ok(image)
     }
 
-    fn create_in_memory() -> Result<Image> {
+    pub(crate) fn create_in_memory() -> Result<Image> {
         let connection = Connection::open_in_memory()?;
 
         connection.execute_batch(include_str!("../sql_scripts/create_image_schema.sql"))?;
 
-        Ok(Image {
+        let mut image = Image {
             path_name: PathBuf::from("/in_memory"),
             file: connection,
-        })
+        };
+        image.migrate()?;
+
+        Ok(image)
     }
 
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Image> {
-        Ok(Image {
+        let mut image = Image {
             path_name: path.as_ref().to_path_buf(),
             file: Connection::open(path)?,
-        })
+        };
+        image.migrate()?;
+
+        Ok(image)
+    }
+
+    /// Keeps the image file to this connection until it's closed, so nothing else can read or
+    /// write it meanwhile.
+    pub fn lock_exclusively(&mut self) -> Result<()> {
+        self.file.pragma_update(None, "locking_mode", "EXCLUSIVE")?;
+
+        // The lock is only taken on the next write, and then held on to
+        match self.file.execute_batch("BEGIN EXCLUSIVE; COMMIT;") {
+            Err(rusqlite::Error::SqliteFailure(error, _))
+                if error.code == ErrorCode::DatabaseBusy =>
+            {
+                Err(ImageInUse)?
+            }
+            locked => Ok(locked?),
+        }
+    }
+
+    fn migrate(&mut self) -> Result<()> {
+        let applied: usize = self
+            .file
+            .pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+            self.transaction(|image| {
+                match migration {
+                    Migration::Script(script) => image.file.execute_batch(script)?,
+                    Migration::Rewrite(rewrite) => rewrite(image)?,
+                }
+                image.file.pragma_update(None, "user_version", index + 1)?;
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Paths were stored as given before `NamespacePath`, so they're repaired once; anything
+    /// that can't be repaired, or would collide once repaired, is moved to `/othismo/lost+found`.
+    fn normalize_namespace_paths(image: &mut Image) -> Result<()> {
+        let mut statement = image
+            .file
+            .prepare("SELECT path, object_key FROM namespace ORDER BY path")?;
+        let entries = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, i64)>>>()?;
+        drop(statement);
+
+        let mut taken: HashSet<String> = entries
+            .iter()
+            .filter(|(path, _)| {
+                NamespacePath::parse(path).is_ok_and(|p| !p.is_root() && p.as_str() == path)
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        for (path, object_key) in entries {
+            if taken.contains(&path) {
+                continue;
+            }
+
+            let repaired = match NamespacePath::repair(&path) {
+                Ok(repaired) if !repaired.is_root() && !taken.contains(&repaired.to_string()) => {
+                    repaired.to_string()
+                }
+                _ => format!("/othismo/lost+found/{}", object_key),
+            };
+
+            image.file.execute(
+                "UPDATE namespace SET path = ? WHERE path = ?",
+                params![repaired, path],
+            )?;
+            taken.insert(repaired);
+        }
+
+        Ok(())
+    }
+
+    /// Runs `work` inside a savepoint, so everything it writes lands together or not at all.
+    /// Savepoints nest, so callers inside `work` may open transactions of their own.
+    pub fn transaction<T>(&mut self, work: impl FnOnce(&mut Image) -> Result<T>) -> Result<T> {
+        self.file.execute_batch("SAVEPOINT othismo")?;
+
+        match work(self) {
+            Ok(value) => {
+                self.file.execute_batch("RELEASE othismo")?;
+                Ok(value)
+            }
+            Err(error) => {
+                self.file
+                    .execute_batch("ROLLBACK TO othismo; RELEASE othismo")?;
+                Err(error)
+            }
+        }
     }
 
     pub fn import_object(&mut self, name: &str, object: Object) -> Result<()> {
-        if self.object_exists(name)? {
+        let path = NamespacePath::parse(name)?;
+        if path.is_root() {
+            Err(OthismoError::InvalidNamespacePath {
+                path: name.to_string(),
+                reason: "objects can't live at the root",
+            })?
+        }
+        if self.object_exists(path.as_str())? {
             return Err(Errors::Othismo(ObjectAlreadyExists));
         }
 
-        self.insert_object(name, object.as_kind_str(), &object.to_bytes())?;
+        self.insert_object(path.as_str(), object.as_kind_str(), &object.to_bytes()?)?;
 
         Ok(())
     }
 
     pub fn get_object(&self, name: &str) -> Result<Object> {
+        let path = NamespacePath::parse(name)?;
         self.file.query_row(
             "select
                 kind, bytes
             from object o
             inner join namespace n on n.object_key = o.object_key
             where n.path = ?",
-            params![name],
+            params![path.as_str()],
             |row| {
                 Ok(Object::from_tuple(
                     row.get::<usize, String>(0).map(|name| name)?.as_str(),
@@ -531,7 +1191,8 @@ impl Image {
     }
 
     pub fn remove_object(&mut self, name: &str) -> Result<()> {
-        let object_key = self.get_object_key(name)?;
+        let path = NamespacePath::parse(name)?;
+        let object_key = self.get_object_key(path.as_str())?;
 
         let references: Option<i64> = self
             .file
@@ -543,7 +1204,7 @@ impl Image {
             inner join object O on O.object_key = L.to_object_key
             inner join namespace NS on NS.object_key = O.object_key
             where NS.path = ?"#,
-                params![name],
+                params![path.as_str()],
                 |row| row.get(0),
             )
             .optional()?;
@@ -559,6 +1220,13 @@ impl Image {
             params![object_key],
         )?;
 
+        self.file.execute(
+            r#"
+        DELETE FROM object_revision where object_key = ?
+        "#,
+            params![object_key],
+        )?;
+
         self.file.execute(
             r#"
         DELETE FROM object where object_key = ?
@@ -566,15 +1234,22 @@ impl Image {
             params![object_key],
         )?;
 
+        self.forget_capabilities_of(path.as_str())?;
+        self.forget_supervision_of(path.as_str())?;
+        self.forget_mailbox_of(path.as_str())?;
+        self.forget_exactly_once_of(path.as_str())?;
+        self.forget_limits_of(path.as_str())?;
+
         Ok(())
     }
 
     pub fn object_exists(&self, name: &str) -> Result<bool> {
+        let path = NamespacePath::parse(name)?;
         let namespace_key: Option<i64> = self
             .file
             .query_row(
                 "select count(*) from namespace where path = ?",
-                params![name],
+                params![path.as_str()],
                 |row| row.get(0),
             )
             .optional()?;
@@ -585,16 +1260,21 @@ impl Image {
         };
     }
 
-    pub fn list_objects(&self, prefix: &str) -> Result<Vec<String>> {
+    /// Every object at or beneath `under`.
+    pub fn list_objects(&self, under: &NamespacePath) -> Result<Vec<String>> {
+        let beneath = match under.is_root() {
+            true => NamespacePath::like_prefix("/"),
+            false => NamespacePath::like_prefix(&format!("{}/", under)),
+        };
         let mut statement = self.file.prepare(
             r#"
             SELECT
                 NS.path
             FROM object O
             INNER JOIN namespace NS on NS.object_key = O.object_key
-            WHERE path LIKE ? || '%'"#,
+            WHERE path = ? OR path LIKE ? ESCAPE '\'"#,
         )?;
-        let mut rows = statement.query(params![prefix])?;
+        let mut rows = statement.query(params![under.as_str(), beneath])?;
 
         let mut names: Vec<String> = Vec::new();
 
@@ -605,12 +1285,33 @@ impl Image {
         Ok(names)
     }
 
+    /// Where every instance lives, without decoding any of them.
+    pub fn list_instances(&self) -> Result<Vec<NamespacePath>> {
+        let mut statement = self.file.prepare(
+            r#"
+            SELECT
+                NS.path
+            FROM object O
+            INNER JOIN namespace NS on NS.object_key = O.object_key
+            WHERE O.kind = 'INSTANCE'"#,
+        )?;
+        let mut rows = statement.query([])?;
+
+        let mut paths = Vec::new();
+        while let Some(row) = rows.next()? {
+            paths.push(NamespacePath::parse(&row.get::<usize, String>(0)?)?);
+        }
+
+        Ok(paths)
+    }
+
     fn get_object_key(&self, name: &str) -> Result<i64> {
+        let path = NamespacePath::parse(name)?;
         let object_key: Option<i64> = self
             .file
             .query_row(
                 "select object_key from namespace where path = ?",
-                params![name],
+                params![path.as_str()],
                 |row| row.get(0),
             )
             .optional()?;
@@ -621,27 +1322,34 @@ impl Image {
         };
     }
 
-    fn insert_object(&mut self, name: &str, kind: &str, bytes: &Vec<u8>) -> Result<()> {
-        self.file.execute(
-            "INSERT INTO object (kind, bytes) VALUES (?, ?)",
-            params![kind, bytes],
-        )?;
-        let row_id = self.file.last_insert_rowid();
-
-        self.upsert_name(name, row_id)?;
-
-        Ok(())
-    }
-
     fn upsert_name(&mut self, name: &str, object_key: i64) -> Result<()> {
+        let path = NamespacePath::parse(name)?;
         self.file.execute(
             "INSERT OR REPLACE INTO namespace (path, object_key) VALUES (?,?)",
-            params![name, object_key],
+            params![path.as_str(), object_key],
         )?;
 
         Ok(())
     }
 }
 
+mod abi;
+mod access_rules;
+mod capabilities;
+mod dead_letters;
+mod exactly_once;
+mod limits;
+mod mailboxes;
+mod modules;
+mod revisions;
+mod supervision;
+
+pub use abi::AbiViolation;
+pub use capabilities::CapabilityGrant;
+pub use dead_letters::DeadLetter;
+pub use exactly_once::OutgoingMail;
+pub use mailboxes::Mail;
+pub use modules::{module_path, ModuleImport, ModuleImportOutcome, MODULES_PATH};
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,185 @@
+use crate::othismo::{OthismoError, Result};
+use std::fmt;
+use wasmer::wasmparser::types::{CoreTypeId, EntityType, Types};
+use wasmer::wasmparser::{FuncType, Parser, Payload, TypeRef, ValType, Validator, WasmFeatures};
+
+const HOST_MODULE: &str = "othismo";
+
+/// Something about a module that keeps it from running as an Othismo instance.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AbiViolation {
+    Invalid(String),
+    MissingExport(&'static str),
+    WrongExportKind {
+        name: &'static str,
+        expected: &'static str,
+    },
+    WrongSignature {
+        name: String,
+        expected: String,
+        found: String,
+    },
+    UnknownImport {
+        module: String,
+        name: String,
+    },
+}
+
+impl fmt::Display for AbiViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AbiViolation::Invalid(reason) => write!(f, "invalid module: {}", reason),
+            AbiViolation::MissingExport(name) => write!(f, "missing export `{}`", name),
+            AbiViolation::WrongExportKind { name, expected } => {
+                write!(f, "export `{}` should be a {}", name, expected)
+            }
+            AbiViolation::WrongSignature {
+                name,
+                expected,
+                found,
+            } => write!(f, "`{}` should be {}, found {}", name, expected, found),
+            AbiViolation::UnknownImport { module, name } => {
+                write!(f, "unknown host import `{}.{}`", module, name)
+            }
+        }
+    }
+}
+
+/// Checks that the module type-checks & speaks the Othismo ABI: it exports `memory`,
+/// `_allocate_message` & `_message_received`, and only imports host functions Othismo provides.
+/// Imported memories, tables & globals are fine, since importing turns them into definitions.
+pub fn validate_module(bytes: &[u8]) -> Result<()> {
+    let mut validator = Validator::new_with_features(WasmFeatures {
+        multi_memory: true,
+        ..WasmFeatures::default()
+    });
+    let types = validator
+        .validate_all(bytes)
+        .map_err(|error| violations(vec![AbiViolation::Invalid(error.message().to_string())]))?;
+
+    let mut problems = Vec::new();
+    let mut imports = Vec::new();
+    let mut exports = Vec::new();
+    for payload in Parser::new(0).parse_all(bytes) {
+        match payload? {
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    let import = import?;
+                    imports.push((
+                        import.module.to_string(),
+                        import.name.to_string(),
+                        import.ty,
+                        types.entity_type_from_import(&import),
+                    ));
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export?;
+                    exports.push((
+                        export.name.to_string(),
+                        types.entity_type_from_export(&export),
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match exports.iter().find(|(name, _)| name == "memory") {
+        Some((_, Some(EntityType::Memory(_)))) => {}
+        Some(_) => problems.push(AbiViolation::WrongExportKind {
+            name: "memory",
+            expected: "memory",
+        }),
+        None => problems.push(AbiViolation::MissingExport("memory")),
+    }
+    let pointer = ValType::I32;
+
+    let required_exports: [(&'static str, FuncType); 2] = [
+        ("_allocate_message", FuncType::new([pointer], [pointer])),
+        ("_message_received", FuncType::new([pointer], [])),
+    ];
+    for (required, expected) in required_exports {
+        match exports.iter().find(|(name, _)| name == required) {
+            Some((_, Some(EntityType::Func(id)))) => {
+                check_signature(
+                    &mut problems,
+                    required,
+                    &expected,
+                    function_type(&types, *id),
+                );
+            }
+            Some(_) => problems.push(AbiViolation::WrongExportKind {
+                name: required,
+                expected: "function",
+            }),
+            None => problems.push(AbiViolation::MissingExport(required)),
+        }
+    }
+
+    let host_functions = [(
+        "_send_message",
+        FuncType::new([pointer, pointer], [pointer]),
+    )];
+    for (module, name, ty, entity) in imports {
+        let host_function = host_functions
+            .iter()
+            .find(|(host_name, _)| module == HOST_MODULE && name == *host_name);
+        match (ty, entity, host_function) {
+            (TypeRef::Func(_), Some(EntityType::Func(id)), Some((_, expected))) => {
+                let qualified = format!("{}.{}", module, name);
+                check_signature(
+                    &mut problems,
+                    &qualified,
+                    expected,
+                    function_type(&types, id),
+                );
+            }
+            (TypeRef::Memory(_) | TypeRef::Table(_) | TypeRef::Global(_), _, _)
+                if module != HOST_MODULE => {}
+            _ => problems.push(AbiViolation::UnknownImport { module, name }),
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(violations(problems))
+    }
+}
+
+fn function_type(types: &Types, id: CoreTypeId) -> &FuncType {
+    types[id].unwrap_func()
+}
+
+fn check_signature(
+    problems: &mut Vec<AbiViolation>,
+    name: &str,
+    expected: &FuncType,
+    found: &FuncType,
+) {
+    if expected != found {
+        problems.push(AbiViolation::WrongSignature {
+            name: name.to_string(),
+            expected: signature(expected),
+            found: signature(found),
+        });
+    }
+}
+
+fn signature(ty: &FuncType) -> String {
+    let list = |types: &[ValType]| {
+        types
+            .iter()
+            .map(|ty| ty.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    format!("({}) -> ({})", list(ty.params()), list(ty.results()))
+}
+
+fn violations(problems: Vec<AbiViolation>) -> crate::othismo::Errors {
+    OthismoError::UnsupportedModuleDefinition(problems).into()
+}
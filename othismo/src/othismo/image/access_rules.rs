@@ -0,0 +1,61 @@
+use super::Image;
+use crate::othismo::acl::{AccessControl, AccessRule, Permission, ANYONE};
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::Result;
+use rusqlite::params;
+
+impl Image {
+    pub fn grant(&mut self, path: &str, principal: &str, permission: Permission) -> Result<()> {
+        let path = NamespacePath::parse(path)?;
+        let principal = normalize_principal(principal)?;
+
+        self.file.execute(
+            "INSERT OR IGNORE INTO access_rule (path, principal, permission) VALUES (?, ?, ?)",
+            params![path.as_str(), principal, permission.as_str()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn revoke(&mut self, path: &str, principal: &str, permission: Permission) -> Result<()> {
+        let path = NamespacePath::parse(path)?;
+        let principal = normalize_principal(principal)?;
+
+        self.file.execute(
+            "DELETE FROM access_rule WHERE path = ? AND principal = ? AND permission = ?",
+            params![path.as_str(), principal, permission.as_str()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn access_rules(&self) -> Result<Vec<AccessRule>> {
+        let mut statement = self.file.prepare(
+            "SELECT path, principal, permission FROM access_rule ORDER BY path, permission, principal",
+        )?;
+        let mut rows = statement.query([])?;
+
+        let mut rules = Vec::new();
+        while let Some(row) = rows.next()? {
+            rules.push(AccessRule {
+                path: NamespacePath::parse(&row.get::<usize, String>(0)?)?,
+                principal: row.get(1)?,
+                permission: Permission::parse(&row.get::<usize, String>(2)?)?,
+            });
+        }
+
+        Ok(rules)
+    }
+
+    pub fn access_control(&self) -> Result<AccessControl> {
+        Ok(AccessControl::new(self.access_rules()?))
+    }
+}
+
+fn normalize_principal(principal: &str) -> Result<String> {
+    if principal == ANYONE {
+        return Ok(principal.to_string());
+    }
+
+    Ok(NamespacePath::parse(principal)?.to_string())
+}
@@ -0,0 +1,145 @@
+use super::Image;
+use crate::othismo::capabilities::{Capabilities, Capability};
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::Result;
+use rusqlite::params;
+
+pub struct CapabilityGrant {
+    pub capability: Capability,
+    pub holder: NamespacePath,
+    pub delivered: bool,
+}
+
+impl Image {
+    /// Mints a capability for `target` and gives it to `holder`; it's handed over in an
+    /// `othismo.capabilities` message the next time the namespace starts.
+    pub fn mint_capability(&mut self, target: &str, holder: &str) -> Result<Capability> {
+        let capability = Capability {
+            token: Capabilities::mint_token(),
+            target: NamespacePath::parse(target)?,
+        };
+        let holder = NamespacePath::parse(holder)?;
+
+        self.transaction(|image| {
+            image.insert_capability(&capability)?;
+            image.give_capability(&capability.token, &holder, false)
+        })?;
+
+        Ok(capability)
+    }
+
+    pub fn insert_capability(&mut self, capability: &Capability) -> Result<()> {
+        self.file.execute(
+            "INSERT OR IGNORE INTO capability (token, target) VALUES (?, ?)",
+            params![capability.token, capability.target.as_str()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn give_capability(
+        &mut self,
+        token: &str,
+        holder: &NamespacePath,
+        delivered: bool,
+    ) -> Result<()> {
+        self.file.execute(
+            "INSERT OR REPLACE INTO capability_holder (token, holder, delivered) VALUES (?, ?, ?)",
+            params![token, holder.as_str(), delivered],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn revoke_capability(&mut self, token: &str) -> Result<()> {
+        self.transaction(|image| {
+            image.file.execute(
+                "DELETE FROM capability_holder WHERE token = ?",
+                params![token],
+            )?;
+            image
+                .file
+                .execute("DELETE FROM capability WHERE token = ?", params![token])?;
+
+            Ok(())
+        })
+    }
+
+    pub fn capability_grants(&self) -> Result<Vec<CapabilityGrant>> {
+        let mut statement = self.file.prepare(
+            r#"
+            SELECT C.token, C.target, H.holder, H.delivered
+            FROM capability C
+            INNER JOIN capability_holder H on H.token = C.token
+            ORDER BY H.holder, C.target"#,
+        )?;
+        let mut rows = statement.query([])?;
+
+        let mut grants = Vec::new();
+        while let Some(row) = rows.next()? {
+            grants.push(CapabilityGrant {
+                capability: Capability {
+                    token: row.get(0)?,
+                    target: NamespacePath::parse(&row.get::<usize, String>(1)?)?,
+                },
+                holder: NamespacePath::parse(&row.get::<usize, String>(2)?)?,
+                delivered: row.get(3)?,
+            });
+        }
+
+        Ok(grants)
+    }
+
+    pub fn set_sandboxed(&mut self, path: &str, sandboxed: bool) -> Result<()> {
+        let path = NamespacePath::parse(path)?;
+
+        if sandboxed {
+            self.file.execute(
+                "INSERT OR IGNORE INTO sandboxed_process (path) VALUES (?)",
+                params![path.as_str()],
+            )?;
+        } else {
+            self.file.execute(
+                "DELETE FROM sandboxed_process WHERE path = ?",
+                params![path.as_str()],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn capabilities(&self) -> Result<Capabilities> {
+        let mut capabilities = Capabilities::default();
+        for grant in self.capability_grants()? {
+            let token = grant.capability.token.clone();
+            capabilities.insert(grant.capability);
+            capabilities.give(&token, &grant.holder);
+        }
+
+        let mut statement = self.file.prepare("SELECT path FROM sandboxed_process")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            capabilities.set_sandboxed(&NamespacePath::parse(&row.get::<usize, String>(0)?)?, true);
+        }
+
+        Ok(capabilities)
+    }
+
+    /// Forgets the capabilities held by or targeting `path`, and whether it was sandboxed.
+    pub(super) fn forget_capabilities_of(&mut self, path: &str) -> Result<()> {
+        self.file.execute(
+            "DELETE FROM sandboxed_process WHERE path = ?",
+            params![path],
+        )?;
+        self.file.execute(
+            r#"
+        DELETE FROM capability_holder
+        WHERE holder = ?1 OR token IN (SELECT token FROM capability WHERE target = ?1)"#,
+            params![path],
+        )?;
+        self.file
+            .execute("DELETE FROM capability WHERE target = ?", params![path])?;
+
+        Ok(())
+    }
+}
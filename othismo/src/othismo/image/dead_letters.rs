@@ -0,0 +1,100 @@
+use super::Image;
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::OthismoError::DeadLetterDoesNotExist;
+use crate::othismo::Result;
+use rusqlite::{params, OptionalExtension, Row};
+
+/// A message the namespace couldn't deliver, kept as it was sent so it can be replayed.
+pub struct DeadLetter {
+    pub id: i64,
+    /// Where it was headed, when that's known; it isn't for unknown capabilities.
+    pub destination: Option<String>,
+    pub sender: Option<String>,
+    pub code: String,
+    pub reason: String,
+    pub message: Vec<u8>,
+    pub received_at: String,
+}
+
+const SELECT_DEAD_LETTERS: &str = r#"
+    SELECT dead_letter_key, destination, sender, code, reason, message,
+           datetime(received_at, 'unixepoch')
+    FROM dead_letter"#;
+
+impl Image {
+    pub fn record_dead_letter(
+        &mut self,
+        destination: Option<&str>,
+        sender: Option<&NamespacePath>,
+        code: &str,
+        reason: &str,
+        message: &[u8],
+    ) -> Result<i64> {
+        self.file.execute(
+            r#"
+        INSERT INTO dead_letter (destination, sender, code, reason, message, received_at)
+        VALUES (?, ?, ?, ?, ?, strftime('%s', 'now'))"#,
+            params![
+                destination,
+                sender.map(NamespacePath::as_str),
+                code,
+                reason,
+                message
+            ],
+        )?;
+
+        Ok(self.file.last_insert_rowid())
+    }
+
+    pub fn dead_letters(&self) -> Result<Vec<DeadLetter>> {
+        let mut statement = self
+            .file
+            .prepare(&format!("{} ORDER BY dead_letter_key", SELECT_DEAD_LETTERS))?;
+        let mut rows = statement.query([])?;
+
+        let mut dead_letters = Vec::new();
+        while let Some(row) = rows.next()? {
+            dead_letters.push(dead_letter_from_row(row)?);
+        }
+
+        Ok(dead_letters)
+    }
+
+    pub fn dead_letter(&self, id: i64) -> Result<DeadLetter> {
+        self.file
+            .query_row(
+                &format!("{} WHERE dead_letter_key = ?", SELECT_DEAD_LETTERS),
+                params![id],
+                dead_letter_from_row,
+            )
+            .optional()?
+            .ok_or(DeadLetterDoesNotExist.into())
+    }
+
+    pub fn remove_dead_letter(&mut self, id: i64) -> Result<()> {
+        match self.file.execute(
+            "DELETE FROM dead_letter WHERE dead_letter_key = ?",
+            params![id],
+        )? {
+            0 => Err(DeadLetterDoesNotExist)?,
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes every dead letter, returning how many there were.
+    pub fn purge_dead_letters(&mut self) -> Result<usize> {
+        Ok(self.file.execute("DELETE FROM dead_letter", [])?)
+    }
+}
+
+fn dead_letter_from_row(row: &Row) -> rusqlite::Result<DeadLetter> {
+    Ok(DeadLetter {
+        id: row.get(0)?,
+        destination: row.get(1)?,
+        sender: row.get(2)?,
+        code: row.get(3)?,
+        reason: row.get(4)?,
+        message: row.get(5)?,
+        received_at: row.get(6)?,
+    })
+}
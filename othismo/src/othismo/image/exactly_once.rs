@@ -0,0 +1,128 @@
+use super::Image;
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::Result;
+use rusqlite::{params, OptionalExtension};
+use std::collections::HashSet;
+
+/// A message an exactly-once process sent while handling mail, kept until the router has it.
+pub struct OutgoingMail {
+    pub key: i64,
+    pub sender: NamespacePath,
+    pub message_id: String,
+    pub message: Vec<u8>,
+}
+
+impl Image {
+    /// Exactly-once processes always have a durable mailbox, so turning it on turns that on too.
+    pub fn set_exactly_once(&mut self, path: &str, exactly_once: bool) -> Result<()> {
+        let path = NamespacePath::parse(path)?;
+
+        self.transaction(|image| {
+            if exactly_once {
+                image.set_durable_mailbox(path.as_str(), true)?;
+                image.file.execute(
+                    "INSERT OR IGNORE INTO exactly_once_process (path) VALUES (?)",
+                    params![path.as_str()],
+                )?;
+            } else {
+                image.file.execute(
+                    "DELETE FROM exactly_once_process WHERE path = ?",
+                    params![path.as_str()],
+                )?;
+            }
+
+            Ok(())
+        })
+    }
+
+    pub fn exactly_once_processes(&self) -> Result<HashSet<NamespacePath>> {
+        let mut statement = self.file.prepare("SELECT path FROM exactly_once_process")?;
+        let mut rows = statement.query([])?;
+
+        let mut paths = HashSet::new();
+        while let Some(row) = rows.next()? {
+            paths.insert(NamespacePath::parse(&row.get::<usize, String>(0)?)?);
+        }
+
+        Ok(paths)
+    }
+
+    pub fn has_processed(&self, recipient: &NamespacePath, message_id: &str) -> Result<bool> {
+        let found: Option<i64> = self
+            .file
+            .query_row(
+                "SELECT 1 FROM processed_message WHERE recipient = ? AND message_id = ?",
+                params![recipient.as_str(), message_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(found.is_some())
+    }
+
+    pub fn mark_processed(&mut self, recipient: &NamespacePath, message_id: &str) -> Result<()> {
+        self.file.execute(
+            r#"
+        INSERT OR IGNORE INTO processed_message (recipient, message_id, processed_at)
+        VALUES (?, ?, strftime('%s', 'now'))"#,
+            params![recipient.as_str(), message_id],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn hold_outgoing_mail(
+        &mut self,
+        sender: &NamespacePath,
+        message_id: &str,
+        message: &[u8],
+    ) -> Result<i64> {
+        self.file.execute(
+            "INSERT INTO outgoing_mail (sender, message_id, message) VALUES (?, ?, ?)",
+            params![sender.as_str(), message_id, message],
+        )?;
+
+        Ok(self.file.last_insert_rowid())
+    }
+
+    pub fn outgoing_mail(&self) -> Result<Vec<OutgoingMail>> {
+        let mut statement = self.file.prepare(
+            "SELECT outgoing_key, sender, message_id, message FROM outgoing_mail ORDER BY outgoing_key",
+        )?;
+        let mut rows = statement.query([])?;
+
+        let mut outgoing = Vec::new();
+        while let Some(row) = rows.next()? {
+            outgoing.push(OutgoingMail {
+                key: row.get(0)?,
+                sender: NamespacePath::parse(&row.get::<usize, String>(1)?)?,
+                message_id: row.get(2)?,
+                message: row.get(3)?,
+            });
+        }
+
+        Ok(outgoing)
+    }
+
+    pub fn release_outgoing_mail(&mut self, key: i64) -> Result<()> {
+        self.file.execute(
+            "DELETE FROM outgoing_mail WHERE outgoing_key = ?",
+            params![key],
+        )?;
+
+        Ok(())
+    }
+
+    pub(super) fn forget_exactly_once_of(&mut self, path: &str) -> Result<()> {
+        self.file.execute(
+            "DELETE FROM exactly_once_process WHERE path = ?",
+            params![path],
+        )?;
+        self.file.execute(
+            "DELETE FROM processed_message WHERE recipient = ?",
+            params![path],
+        )?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use super::Image;
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::Result;
+use rusqlite::params;
+use std::collections::HashMap;
+use std::time::Duration;
+
+impl Image {
+    /// Caps how much fuel the instance at `path` may burn on each message, or lifts the cap
+    /// with `None`.
+    pub fn set_fuel_budget(&mut self, path: &str, fuel: Option<u64>) -> Result<()> {
+        let path = NamespacePath::parse(path)?;
+
+        match fuel {
+            Some(fuel) => self.file.execute(
+                r#"
+            INSERT INTO fuel_budget (path, fuel) VALUES (?, ?)
+            ON CONFLICT (path) DO UPDATE SET fuel = excluded.fuel"#,
+                params![path.as_str(), fuel],
+            )?,
+            None => self.file.execute(
+                "DELETE FROM fuel_budget WHERE path = ?",
+                params![path.as_str()],
+            )?,
+        };
+
+        Ok(())
+    }
+
+    pub fn fuel_budgets(&self) -> Result<HashMap<NamespacePath, u64>> {
+        let mut statement = self.file.prepare("SELECT path, fuel FROM fuel_budget")?;
+        let mut rows = statement.query([])?;
+
+        let mut budgets = HashMap::new();
+        while let Some(row) = rows.next()? {
+            budgets.insert(
+                NamespacePath::parse(&row.get::<usize, String>(0)?)?,
+                row.get(1)?,
+            );
+        }
+
+        Ok(budgets)
+    }
+
+    /// Gives the instance at `path` at most `deadline` to handle each message, or as long as it
+    /// takes with `None`.
+    pub fn set_execution_deadline(&mut self, path: &str, deadline: Option<Duration>) -> Result<()> {
+        let path = NamespacePath::parse(path)?;
+
+        match deadline {
+            Some(deadline) => self.file.execute(
+                r#"
+            INSERT INTO execution_deadline (path, milliseconds) VALUES (?, ?)
+            ON CONFLICT (path) DO UPDATE SET milliseconds = excluded.milliseconds"#,
+                params![path.as_str(), deadline.as_millis() as u64],
+            )?,
+            None => self.file.execute(
+                "DELETE FROM execution_deadline WHERE path = ?",
+                params![path.as_str()],
+            )?,
+        };
+
+        Ok(())
+    }
+
+    pub fn execution_deadlines(&self) -> Result<HashMap<NamespacePath, Duration>> {
+        let mut statement = self
+            .file
+            .prepare("SELECT path, milliseconds FROM execution_deadline")?;
+        let mut rows = statement.query([])?;
+
+        let mut deadlines = HashMap::new();
+        while let Some(row) = rows.next()? {
+            deadlines.insert(
+                NamespacePath::parse(&row.get::<usize, String>(0)?)?,
+                Duration::from_millis(row.get(1)?),
+            );
+        }
+
+        Ok(deadlines)
+    }
+
+    /// Caps each memory of the instance at `path` at `pages`, or lifts the cap with `None`.
+    pub fn set_memory_limit(&mut self, path: &str, pages: Option<u32>) -> Result<()> {
+        let path = NamespacePath::parse(path)?;
+
+        match pages {
+            Some(pages) => self.file.execute(
+                r#"
+            INSERT INTO memory_limit (path, pages) VALUES (?, ?)
+            ON CONFLICT (path) DO UPDATE SET pages = excluded.pages"#,
+                params![path.as_str(), pages],
+            )?,
+            None => self.file.execute(
+                "DELETE FROM memory_limit WHERE path = ?",
+                params![path.as_str()],
+            )?,
+        };
+
+        Ok(())
+    }
+
+    pub fn memory_limits(&self) -> Result<HashMap<NamespacePath, u32>> {
+        let mut statement = self.file.prepare("SELECT path, pages FROM memory_limit")?;
+        let mut rows = statement.query([])?;
+
+        let mut limits = HashMap::new();
+        while let Some(row) = rows.next()? {
+            limits.insert(
+                NamespacePath::parse(&row.get::<usize, String>(0)?)?,
+                row.get(1)?,
+            );
+        }
+
+        Ok(limits)
+    }
+
+    /// Hibernates the instance at `path` once it's gone `period` without mail, or keeps it
+    /// running for as long as the namespace is with `None`.
+    pub fn set_hibernation_period(&mut self, path: &str, period: Option<Duration>) -> Result<()> {
+        let path = NamespacePath::parse(path)?;
+
+        match period {
+            Some(period) => self.file.execute(
+                r#"
+            INSERT INTO hibernation_period (path, milliseconds) VALUES (?, ?)
+            ON CONFLICT (path) DO UPDATE SET milliseconds = excluded.milliseconds"#,
+                params![path.as_str(), period.as_millis() as u64],
+            )?,
+            None => self.file.execute(
+                "DELETE FROM hibernation_period WHERE path = ?",
+                params![path.as_str()],
+            )?,
+        };
+
+        Ok(())
+    }
+
+    pub fn hibernation_periods(&self) -> Result<HashMap<NamespacePath, Duration>> {
+        let mut statement = self
+            .file
+            .prepare("SELECT path, milliseconds FROM hibernation_period")?;
+        let mut rows = statement.query([])?;
+
+        let mut periods = HashMap::new();
+        while let Some(row) = rows.next()? {
+            periods.insert(
+                NamespacePath::parse(&row.get::<usize, String>(0)?)?,
+                Duration::from_millis(row.get(1)?),
+            );
+        }
+
+        Ok(periods)
+    }
+
+    pub(super) fn forget_limits_of(&mut self, path: &str) -> Result<()> {
+        self.file
+            .execute("DELETE FROM fuel_budget WHERE path = ?", params![path])?;
+        self.file.execute(
+            "DELETE FROM execution_deadline WHERE path = ?",
+            params![path],
+        )?;
+        self.file
+            .execute("DELETE FROM memory_limit WHERE path = ?", params![path])?;
+        self.file.execute(
+            "DELETE FROM hibernation_period WHERE path = ?",
+            params![path],
+        )?;
+
+        Ok(())
+    }
+}
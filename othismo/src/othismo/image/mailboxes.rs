@@ -0,0 +1,152 @@
+use super::Image;
+use crate::othismo::mailbox::{MailboxLimit, Overflow};
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::Result;
+use rusqlite::params;
+use std::collections::{HashMap, HashSet};
+
+/// A message waiting in a durable mailbox, already routed & stamped.
+pub struct Mail {
+    pub key: i64,
+    pub sender: Option<NamespacePath>,
+    pub message: Vec<u8>,
+}
+
+impl Image {
+    pub fn set_durable_mailbox(&mut self, path: &str, durable: bool) -> Result<()> {
+        let path = NamespacePath::parse(path)?;
+
+        if durable {
+            self.file.execute(
+                "INSERT OR IGNORE INTO durable_mailbox (path) VALUES (?)",
+                params![path.as_str()],
+            )?;
+        } else {
+            self.transaction(|image| {
+                image.file.execute(
+                    "DELETE FROM durable_mailbox WHERE path = ?",
+                    params![path.as_str()],
+                )?;
+                image.file.execute(
+                    "DELETE FROM mail WHERE recipient = ?",
+                    params![path.as_str()],
+                )?;
+
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+
+    pub fn durable_mailboxes(&self) -> Result<HashSet<NamespacePath>> {
+        let mut statement = self.file.prepare("SELECT path FROM durable_mailbox")?;
+        let mut rows = statement.query([])?;
+
+        let mut paths = HashSet::new();
+        while let Some(row) = rows.next()? {
+            paths.insert(NamespacePath::parse(&row.get::<usize, String>(0)?)?);
+        }
+
+        Ok(paths)
+    }
+
+    /// Bounds how many messages `path` may have waiting, or lifts the bound with `None`.
+    pub fn set_mailbox_limit(&mut self, path: &str, limit: Option<MailboxLimit>) -> Result<()> {
+        let path = NamespacePath::parse(path)?;
+
+        match limit {
+            Some(limit) => self.file.execute(
+                r#"
+            INSERT INTO mailbox_limit (path, capacity, overflow) VALUES (?, ?, ?)
+            ON CONFLICT (path) DO UPDATE SET capacity = excluded.capacity, overflow = excluded.overflow"#,
+                params![path.as_str(), limit.capacity, limit.overflow.as_str()],
+            )?,
+            None => self.file.execute(
+                "DELETE FROM mailbox_limit WHERE path = ?",
+                params![path.as_str()],
+            )?,
+        };
+
+        Ok(())
+    }
+
+    pub fn mailbox_limits(&self) -> Result<HashMap<NamespacePath, MailboxLimit>> {
+        let mut statement = self
+            .file
+            .prepare("SELECT path, capacity, overflow FROM mailbox_limit")?;
+        let mut rows = statement.query([])?;
+
+        let mut limits = HashMap::new();
+        while let Some(row) = rows.next()? {
+            limits.insert(
+                NamespacePath::parse(&row.get::<usize, String>(0)?)?,
+                MailboxLimit {
+                    capacity: row.get(1)?,
+                    overflow: Overflow::parse(&row.get::<usize, String>(2)?)?,
+                },
+            );
+        }
+
+        Ok(limits)
+    }
+
+    /// Keeps a message for `recipient` until it's acknowledged, returning its key.
+    pub fn enqueue_mail(
+        &mut self,
+        recipient: &NamespacePath,
+        sender: Option<&NamespacePath>,
+        message: &[u8],
+    ) -> Result<i64> {
+        self.file.execute(
+            r#"
+        INSERT INTO mail (recipient, sender, message, queued_at)
+        VALUES (?, ?, ?, strftime('%s', 'now'))"#,
+            params![
+                recipient.as_str(),
+                sender.map(NamespacePath::as_str),
+                message
+            ],
+        )?;
+
+        Ok(self.file.last_insert_rowid())
+    }
+
+    /// The messages `recipient` hasn't acknowledged yet, oldest first.
+    pub fn pending_mail(&self, recipient: &NamespacePath) -> Result<Vec<Mail>> {
+        let mut statement = self.file.prepare(
+            "SELECT mail_key, sender, message FROM mail WHERE recipient = ? ORDER BY mail_key",
+        )?;
+        let mut rows = statement.query(params![recipient.as_str()])?;
+
+        let mut mail = Vec::new();
+        while let Some(row) = rows.next()? {
+            let sender: Option<String> = row.get(1)?;
+            mail.push(Mail {
+                key: row.get(0)?,
+                sender: sender.as_deref().map(NamespacePath::parse).transpose()?,
+                message: row.get(2)?,
+            });
+        }
+
+        Ok(mail)
+    }
+
+    pub fn acknowledge_mail(&mut self, key: i64) -> Result<()> {
+        self.file
+            .execute("DELETE FROM mail WHERE mail_key = ?", params![key])?;
+
+        Ok(())
+    }
+
+    pub(super) fn forget_mailbox_of(&mut self, path: &str) -> Result<()> {
+        self.file
+            .execute("DELETE FROM durable_mailbox WHERE path = ?", params![path])?;
+        self.file
+            .execute("DELETE FROM mail WHERE recipient = ?", params![path])?;
+        self.file
+            .execute("DELETE FROM mailbox_limit WHERE path = ?", params![path])?;
+
+        Ok(())
+    }
+}
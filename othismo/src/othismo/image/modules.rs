@@ -0,0 +1,137 @@
+use super::{Image, Object};
+use crate::othismo::OthismoError::ObjectAlreadyExists;
+use crate::othismo::{Errors, Result};
+use std::path::{Component, Path, PathBuf};
+
+pub const MODULES_PATH: &str = "/othismo/modules";
+const MODULE_EXTENSIONS: &[&str] = &["wasm", "wat", "wast"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModuleImportOutcome {
+    Imported,
+    Replaced,
+    Unchanged,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleImport {
+    pub file: PathBuf,
+    pub name: String,
+    pub outcome: ModuleImportOutcome,
+}
+
+impl Object {
+    /// Reads a module from a `.wasm` binary, or compiles it from `.wat`/`.wast` text.
+    pub fn read_module(file: &Path) -> Result<Object> {
+        match file.extension().and_then(|extension| extension.to_str()) {
+            Some("wat" | "wast") => Object::new_module_from_wat(&std::fs::read_to_string(file)?),
+            _ => Object::new_module(&std::fs::read(file)?),
+        }
+    }
+}
+
+impl Image {
+    /// Imports a module file, or every module file beneath a directory, in one transaction.
+    ///
+    /// Without a `destination`, `./foo/fizzbuzz.wasm` lands at `/othismo/modules/foo/fizzbuzz`.
+    /// A file's `destination` is its exact name, a directory's is the root its tree lands under.
+    /// With `replace`, modules that already exist are upgraded when their contents have changed.
+    pub fn import_modules(
+        &mut self,
+        source: &Path,
+        destination: Option<&str>,
+        replace: bool,
+    ) -> Result<Vec<ModuleImport>> {
+        let files = if source.is_dir() {
+            let mut files = Vec::new();
+            find_module_files(source, &mut files)?;
+            files
+        } else {
+            vec![source.to_path_buf()]
+        };
+
+        let mut names = Vec::new();
+        for file in files {
+            let name = match destination {
+                None => module_path(MODULES_PATH, &file),
+                Some(destination) if source.is_dir() => {
+                    module_path(destination, file.strip_prefix(source).unwrap_or(&file))
+                }
+                Some(destination) => destination.to_string(),
+            };
+            names.push((file, name));
+        }
+
+        self.transaction(|image| {
+            let mut imports = Vec::new();
+            for (file, name) in names {
+                let outcome = image.import_module(&name, Object::read_module(&file)?, replace)?;
+                imports.push(ModuleImport {
+                    file,
+                    name,
+                    outcome,
+                });
+            }
+
+            Ok(imports)
+        })
+    }
+
+    fn import_module(
+        &mut self,
+        name: &str,
+        module: Object,
+        replace: bool,
+    ) -> Result<ModuleImportOutcome> {
+        if !self.object_exists(name)? {
+            self.import_object(name, module)?;
+            return Ok(ModuleImportOutcome::Imported);
+        }
+
+        match self.get_object(name)? {
+            Object::Module(_) if !replace => Err(Errors::Othismo(ObjectAlreadyExists)),
+            Object::Module(existing) if existing.to_bytes()? == module.to_bytes()? => {
+                Ok(ModuleImportOutcome::Unchanged)
+            }
+            Object::Module(_) => {
+                self.replace_object(name, module)?;
+                Ok(ModuleImportOutcome::Replaced)
+            }
+            Object::Instance(_) => Err(Errors::Othismo(ObjectAlreadyExists)),
+        }
+    }
+}
+
+/// Names a module file under `root` after its path, less its extension; `.` & `..` are dropped.
+pub fn module_path(root: &str, file: &Path) -> String {
+    let mut path = root.trim_end_matches('/').to_string();
+    for component in file.with_extension("").components() {
+        if let Component::Normal(part) = component {
+            path.push('/');
+            path.push_str(&part.to_string_lossy());
+        }
+    }
+
+    path
+}
+
+fn find_module_files(directory: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = std::fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() {
+            find_module_files(&entry, files)?;
+        } else if entry
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| MODULE_EXTENSIONS.contains(&extension))
+        {
+            files.push(entry);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use super::{Image, Object};
+use crate::othismo::OthismoError::{ObjectDoesNotExist, RevisionDoesNotExist};
+use crate::othismo::{Errors, Result};
+use rusqlite::{params, OptionalExtension};
+
+// Every write to an object row goes through here; the row in `object` is always the current
+// revision and `object_revision` holds the ones it replaced.
+
+const HISTORY_RETENTION_SETTING: &str = "history_retention";
+const DEFAULT_HISTORY_RETENTION: u32 = 16;
+
+pub struct ObjectRevision {
+    pub revision: i64,
+    pub revised_at: String,
+    pub size: usize,
+    pub current: bool,
+}
+
+impl Image {
+    pub(super) fn insert_object(&mut self, name: &str, kind: &str, bytes: &Vec<u8>) -> Result<()> {
+        self.file.execute(
+            "INSERT INTO object (kind, bytes, revised_at) VALUES (?, ?, strftime('%s', 'now'))",
+            params![kind, bytes],
+        )?;
+        let row_id = self.file.last_insert_rowid();
+
+        self.upsert_name(name, row_id)?;
+
+        Ok(())
+    }
+
+    pub fn replace_object(&mut self, name: &str, object: Object) -> Result<()> {
+        let object_key = self.get_object_key(name)?;
+
+        let bytes = object.to_bytes()?;
+
+        self.transaction(|image| {
+            image.archive_current_revision(object_key)?;
+            image.file.execute(
+                r#"
+            UPDATE object
+            SET kind = ?, bytes = ?, revision = revision + 1, revised_at = strftime('%s', 'now')
+            WHERE object_key = ?"#,
+                params![object.as_kind_str(), bytes, object_key],
+            )?;
+            image.prune_revisions(object_key)
+        })
+    }
+
+    pub fn history(&self, name: &str) -> Result<Vec<ObjectRevision>> {
+        let object_key = self.get_object_key(name)?;
+
+        let mut statement = self.file.prepare(
+            r#"
+            SELECT revision, datetime(revised_at, 'unixepoch'), length(bytes), 1
+            FROM object
+            WHERE object_key = ?1
+            UNION ALL
+            SELECT revision, datetime(revised_at, 'unixepoch'), length(bytes), 0
+            FROM object_revision
+            WHERE object_key = ?1
+            ORDER BY 1 DESC"#,
+        )?;
+        let mut rows = statement.query(params![object_key])?;
+
+        let mut revisions = Vec::new();
+        while let Some(row) = rows.next()? {
+            revisions.push(ObjectRevision {
+                revision: row.get(0)?,
+                revised_at: row.get(1)?,
+                size: row.get(2)?,
+                current: row.get(3)?,
+            });
+        }
+
+        Ok(revisions)
+    }
+
+    pub fn get_object_revision(&self, name: &str, revision: i64) -> Result<Object> {
+        let object_key = self.get_object_key(name)?;
+
+        let found: Option<(String, Vec<u8>)> = self
+            .file
+            .query_row(
+                r#"
+            SELECT kind, bytes FROM object WHERE object_key = ?1 AND revision = ?2
+            UNION ALL
+            SELECT kind, bytes FROM object_revision WHERE object_key = ?1 AND revision = ?2"#,
+                params![object_key, revision],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        match found {
+            Some((kind, bytes)) => Object::from_tuple(&kind, bytes),
+            None => Err(Errors::Othismo(RevisionDoesNotExist)),
+        }
+    }
+
+    /// Reverting never rewrites history; the old revision comes back as a brand new one.
+    pub fn revert_object(&mut self, name: &str, revision: i64) -> Result<()> {
+        let object = self.get_object_revision(name, revision)?;
+
+        self.replace_object(name, object)
+    }
+
+    pub fn history_retention(&self) -> Result<u32> {
+        let retention: Option<u32> = self
+            .file
+            .query_row(
+                "SELECT value FROM setting WHERE key = ?",
+                params![HISTORY_RETENTION_SETTING],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(retention.unwrap_or(DEFAULT_HISTORY_RETENTION))
+    }
+
+    pub fn set_history_retention(&mut self, retention: u32) -> Result<()> {
+        self.file.execute(
+            "INSERT OR REPLACE INTO setting (key, value) VALUES (?, ?)",
+            params![HISTORY_RETENTION_SETTING, retention],
+        )?;
+
+        let mut statement = self.file.prepare("SELECT object_key FROM object")?;
+        let object_keys = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+        drop(statement);
+
+        for object_key in object_keys {
+            self.prune_revisions(object_key)?;
+        }
+
+        Ok(())
+    }
+
+    fn archive_current_revision(&mut self, object_key: i64) -> Result<()> {
+        let archived = self.file.execute(
+            r#"
+            INSERT INTO object_revision (object_key, revision, kind, bytes, revised_at)
+            SELECT object_key, revision, kind, bytes, revised_at
+            FROM object
+            WHERE object_key = ?"#,
+            params![object_key],
+        )?;
+
+        if archived == 0 {
+            return Err(Errors::Othismo(ObjectDoesNotExist));
+        }
+
+        Ok(())
+    }
+
+    fn prune_revisions(&mut self, object_key: i64) -> Result<()> {
+        let retention = self.history_retention()?;
+
+        self.file.execute(
+            r#"
+            DELETE FROM object_revision
+            WHERE object_key = ?1
+            AND revision NOT IN (
+                SELECT revision
+                FROM object_revision
+                WHERE object_key = ?1
+                ORDER BY revision DESC
+                LIMIT ?2
+            )"#,
+            params![object_key, retention],
+        )?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use super::Image;
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::supervision::{RestartPolicy, Supervision};
+use crate::othismo::Result;
+use rusqlite::{params, OptionalExtension};
+use std::collections::HashMap;
+use std::time::Duration;
+
+impl Image {
+    pub fn set_supervision(&mut self, path: &str, supervision: &Supervision) -> Result<()> {
+        let path = NamespacePath::parse(path)?;
+
+        self.file.execute(
+            r#"
+        INSERT OR REPLACE INTO supervision (path, policy, max_restarts, within_seconds)
+        VALUES (?, ?, ?, ?)"#,
+            params![
+                path.as_str(),
+                supervision.policy.as_str(),
+                supervision.max_restarts,
+                supervision.within.as_secs()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// How the process at `path` is supervised; permanent, with the default limits, unless
+    /// set otherwise.
+    pub fn supervision(&self, path: &str) -> Result<Supervision> {
+        let path = NamespacePath::parse(path)?;
+        let row: Option<(String, u32, u64)> = self
+            .file
+            .query_row(
+                "SELECT policy, max_restarts, within_seconds FROM supervision WHERE path = ?",
+                params![path.as_str()],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+
+        match row {
+            Some((policy, max_restarts, within_seconds)) => Ok(Supervision {
+                policy: RestartPolicy::parse(&policy)?,
+                max_restarts,
+                within: Duration::from_secs(within_seconds),
+            }),
+            None => Ok(Supervision::default()),
+        }
+    }
+
+    /// How every process that isn't supervised the default way is supervised.
+    pub fn supervisions(&self) -> Result<HashMap<NamespacePath, Supervision>> {
+        let mut statement = self
+            .file
+            .prepare("SELECT path, policy, max_restarts, within_seconds FROM supervision")?;
+        let mut rows = statement.query([])?;
+
+        let mut supervisions = HashMap::new();
+        while let Some(row) = rows.next()? {
+            supervisions.insert(
+                NamespacePath::parse(&row.get::<usize, String>(0)?)?,
+                Supervision {
+                    policy: RestartPolicy::parse(&row.get::<usize, String>(1)?)?,
+                    max_restarts: row.get(2)?,
+                    within: Duration::from_secs(row.get(3)?),
+                },
+            );
+        }
+
+        Ok(supervisions)
+    }
+
+    pub(super) fn forget_supervision_of(&mut self, path: &str) -> Result<()> {
+        self.file
+            .execute("DELETE FROM supervision WHERE path = ?", params![path])?;
+
+        Ok(())
+    }
+}
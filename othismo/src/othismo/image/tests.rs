@@ -1,10 +1,20 @@
 use lazy_static::lazy_static;
+use crate::othismo::acl::Permission;
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::mailbox::{MailboxLimit, Overflow};
+use crate::othismo::supervision::{RestartPolicy, Supervision};
+use std::time::Duration;
 use crate::othismo::{Errors, OthismoError};
-use crate::othismo::image::{Image, Object};
+use crate::othismo::image::{
+    module_path, AbiViolation, Image, InstanceAtRest, ModuleImportOutcome, Object, MODULES_PATH,
+};
 
 lazy_static! {
     static ref WASM: Vec<u8> = {
         match wasmer::wat2wasm(r#"(module
+            (memory (export "memory") 1)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32))
             (func (export "addTwo") (param i32 i32) (result i32)
                 local.get 0
                 local.get 1
@@ -22,7 +32,7 @@ fn file_can_import_modules() {
 
     file.import_object("/test/module", Object::new_module(&WASM).unwrap()).unwrap();
 
-    let modules = file.list_objects("/test/").unwrap();
+    let modules = file.list_objects(&NamespacePath::parse("/test").unwrap()).unwrap();
 
     assert_eq!(modules.len(), 1);
     assert_eq!(modules[0], "/test/module");
@@ -47,3 +57,640 @@ fn file_can_delete_modules() {
 
     file.remove_object("/test/module").unwrap();
 }
+
+#[test]
+fn file_keeps_previous_revisions_of_objects() {
+    let mut file = Image::create_in_memory().unwrap();
+
+    file.import_object("/test/module", Object::new_module(&WASM).unwrap()).unwrap();
+    file.replace_object("/test/module", Object::new_module(&WASM).unwrap()).unwrap();
+    file.replace_object("/test/module", Object::new_module(&WASM).unwrap()).unwrap();
+
+    let revisions: Vec<i64> = file.history("/test/module").unwrap().iter().map(|r| r.revision).collect();
+
+    assert_eq!(revisions, vec![3, 2, 1]);
+}
+
+#[test]
+fn file_can_revert_to_a_previous_revision() {
+    let mut file = Image::create_in_memory().unwrap();
+    let module = Object::new_module(&WASM).unwrap();
+    let instance = Object::new_instance(&module).unwrap();
+
+    file.import_object("/test/object", module).unwrap();
+    file.replace_object("/test/object", instance).unwrap();
+    file.revert_object("/test/object", 1).unwrap();
+
+    let history = file.history("/test/object").unwrap();
+
+    assert_eq!(history[0].revision, 3);
+    assert!(matches!(file.get_object("/test/object").unwrap(), Object::Module(_)));
+}
+
+#[test]
+fn file_prunes_revisions_beyond_retention() {
+    let mut file = Image::create_in_memory().unwrap();
+    file.set_history_retention(1).unwrap();
+
+    file.import_object("/test/module", Object::new_module(&WASM).unwrap()).unwrap();
+    for _ in 0..3 {
+        file.replace_object("/test/module", Object::new_module(&WASM).unwrap()).unwrap();
+    }
+
+    let revisions: Vec<i64> = file.history("/test/module").unwrap().iter().map(|r| r.revision).collect();
+
+    assert_eq!(revisions, vec![4, 3]);
+    assert!(matches!(
+        file.revert_object("/test/module", 1),
+        Err(Errors::Othismo(OthismoError::RevisionDoesNotExist))
+    ));
+}
+
+#[test]
+fn importing_exports_every_memory() {
+    let wasm = wasmer::wat2wasm(
+        r#"(module
+            (import "env" "first" (memory 1))
+            (import "env" "second" (memory 2))
+            (memory (export "memory") 3)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32)))
+        "#
+        .as_bytes(),
+    )
+    .unwrap();
+
+    let module = match Object::new_module(&wasm.to_vec()).unwrap() {
+        Object::Module(module) => module,
+        _ => panic!("expected a module"),
+    };
+    let instance: InstanceAtRest = module.into();
+
+    let memories: Vec<(u32, String)> = instance
+        .exported_memories()
+        .unwrap()
+        .into_iter()
+        .map(|(id, name)| (id.index, name))
+        .collect();
+
+    assert_eq!(
+        memories,
+        vec![
+            (2, "memory".to_string()),
+            (0, "othismo_memory_0".to_string()),
+            (1, "othismo_memory_1".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn importing_rejects_64_bit_memories() {
+    // wasmer can't compile them, so they're turned away before they reach the image
+    let wasm = wasmer::wat2wasm(
+        r#"(module
+            (import "env" "heap" (memory i64 1))
+            (memory (export "memory") 1)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32)))
+        "#
+        .as_bytes(),
+    )
+    .unwrap();
+
+    assert!(matches!(
+        Object::new_module(&wasm.to_vec()),
+        Err(Errors::Othismo(OthismoError::UnsupportedModuleDefinition(problems)))
+            if matches!(problems[..], [AbiViolation::Invalid(_)])
+    ));
+}
+
+#[test]
+fn snapshots_cannot_resize_memories_that_do_not_exist() {
+    let mut instance: InstanceAtRest = match Object::new_module(&WASM).unwrap() {
+        Object::Module(module) => module.into(),
+        _ => panic!("expected a module"),
+    };
+
+    assert!(matches!(
+        instance.resize_memory(wasmbin::indices::MemId { index: 7 }, 65536),
+        Err(Errors::Othismo(OthismoError::NoSuchMemory(7)))
+    ));
+}
+
+#[test]
+fn importing_from_wat_keeps_the_source() {
+    let source = r#"(module
+        (memory (export "memory") 1)
+        (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+        (func (export "_message_received") (param i32))
+        (func (export "addTwo") (param i32 i32) (result i32)
+            local.get 0
+            local.get 1
+            i32.add))
+    "#;
+    let mut file = Image::create_in_memory().unwrap();
+
+    let module = Object::new_module_from_wat(source).unwrap();
+    file.import_object("/test/instance", Object::new_instance(&module).unwrap()).unwrap();
+
+    let instance = file.get_object("/test/instance").unwrap();
+
+    assert_eq!(instance.source().unwrap().as_deref(), Some(source));
+    assert_eq!(Object::new_module(&WASM).unwrap().source().unwrap(), None);
+}
+
+#[test]
+fn importing_invalid_wat_fails() {
+    let result = Object::new_module_from_wat("(module (func (export \"broken\") i32.add");
+
+    assert!(matches!(result, Err(Errors::Wat(_))));
+}
+
+#[test]
+fn importing_rejects_modules_that_break_the_abi() {
+    let wasm = wasmer::wat2wasm(
+        r#"(module
+            (import "othismo" "_send_message" (func (param i32) (result i32)))
+            (import "othismo" "_spawn" (func))
+            (import "env" "log" (func (param i32)))
+            (func (export "_allocate_message") (param i64) (result i64) i64.const 0))
+        "#
+        .as_bytes(),
+    )
+    .unwrap();
+
+    let problems = match Object::new_module(&wasm.to_vec()) {
+        Err(Errors::Othismo(OthismoError::UnsupportedModuleDefinition(problems))) => problems,
+        _ => panic!("expected the module to be rejected"),
+    };
+
+    assert_eq!(
+        problems,
+        vec![
+            AbiViolation::MissingExport("memory"),
+            AbiViolation::WrongSignature {
+                name: "_allocate_message".to_string(),
+                expected: "(i32) -> (i32)".to_string(),
+                found: "(i64) -> (i64)".to_string(),
+            },
+            AbiViolation::MissingExport("_message_received"),
+            AbiViolation::WrongSignature {
+                name: "othismo._send_message".to_string(),
+                expected: "(i32, i32) -> (i32)".to_string(),
+                found: "(i32) -> (i32)".to_string(),
+            },
+            AbiViolation::UnknownImport {
+                module: "othismo".to_string(),
+                name: "_spawn".to_string(),
+            },
+            AbiViolation::UnknownImport {
+                module: "env".to_string(),
+                name: "log".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn importing_rejects_modules_that_do_not_type_check() {
+    let wasm = wasmer::wat2wasm(
+        r#"(module (func (export "broken") (result i32) i64.const 0))"#.as_bytes(),
+    )
+    .unwrap();
+
+    assert!(matches!(
+        Object::new_module(&wasm.to_vec()),
+        Err(Errors::Othismo(OthismoError::UnsupportedModuleDefinition(problems)))
+            if matches!(problems[..], [AbiViolation::Invalid(_)])
+    ));
+}
+
+fn module_directory(name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+    let directory = std::env::temp_dir().join(format!("othismo-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    for (file, contents) in files {
+        let path = directory.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    directory
+}
+
+const WAT: &str = r#"(module
+    (memory (export "memory") 1)
+    (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+    (func (export "_message_received") (param i32)))
+"#;
+
+#[test]
+fn modules_default_to_a_matching_path_under_othismo_modules() {
+    assert_eq!(
+        module_path(MODULES_PATH, std::path::Path::new("./foo/fizzbuzz.wasm")),
+        "/othismo/modules/foo/fizzbuzz"
+    );
+    assert_eq!(
+        module_path("/custom/", std::path::Path::new("bar/baz.wat")),
+        "/custom/bar/baz"
+    );
+}
+
+#[test]
+fn file_can_import_a_directory_of_modules() {
+    let directory = module_directory("import", &[
+        ("top.wat", WAT),
+        ("nested/deeper/inner.wat", WAT),
+        ("notes.txt", "not a module"),
+    ]);
+    let mut file = Image::create_in_memory().unwrap();
+
+    let imports = file.import_modules(&directory, Some("/mods"), false).unwrap();
+
+    let names: Vec<String> = imports.iter().map(|import| import.name.clone()).collect();
+    assert_eq!(names, vec!["/mods/nested/deeper/inner", "/mods/top"]);
+    assert!(imports.iter().all(|import| import.outcome == ModuleImportOutcome::Imported));
+    assert_eq!(file.list_objects(&NamespacePath::parse("/mods").unwrap()).unwrap().len(), 2);
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn importing_a_directory_is_all_or_nothing() {
+    let directory = module_directory("atomic", &[
+        ("a_good.wat", WAT),
+        ("b_broken.wat", "(module)"),
+    ]);
+    let mut file = Image::create_in_memory().unwrap();
+
+    assert!(file.import_modules(&directory, Some("/mods"), false).is_err());
+    assert!(file.list_objects(&NamespacePath::parse("/mods").unwrap()).unwrap().is_empty());
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn importing_with_replace_upgrades_changed_modules() {
+    let directory = module_directory("replace", &[("same.wat", WAT), ("changed.wat", WAT)]);
+    let mut file = Image::create_in_memory().unwrap();
+    file.import_modules(&directory, Some("/mods"), false).unwrap();
+
+    let changed = WAT.replace("i32.const 1024", "i32.const 2048");
+    std::fs::write(directory.join("changed.wat"), changed).unwrap();
+
+    assert!(matches!(
+        file.import_modules(&directory, Some("/mods"), false),
+        Err(Errors::Othismo(OthismoError::ObjectAlreadyExists))
+    ));
+    let outcomes: Vec<(String, ModuleImportOutcome)> = file
+        .import_modules(&directory, Some("/mods"), true)
+        .unwrap()
+        .into_iter()
+        .map(|import| (import.name, import.outcome))
+        .collect();
+
+    assert_eq!(outcomes, vec![
+        ("/mods/changed".to_string(), ModuleImportOutcome::Replaced),
+        ("/mods/same".to_string(), ModuleImportOutcome::Unchanged),
+    ]);
+    assert_eq!(file.history("/mods/changed").unwrap().len(), 2);
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn file_rejects_malformed_paths() {
+    let mut file = Image::create_in_memory().unwrap();
+
+    for path in ["", "prototype", "/a/../b", "/"] {
+        assert!(matches!(
+            file.import_object(path, Object::new_module(&WASM).unwrap()),
+            Err(Errors::Othismo(OthismoError::InvalidNamespacePath { .. }))
+        ));
+    }
+}
+
+#[test]
+fn file_normalizes_paths() {
+    let mut file = Image::create_in_memory().unwrap();
+
+    file.import_object("//test//module/", Object::new_module(&WASM).unwrap()).unwrap();
+
+    assert_eq!(file.list_objects(&NamespacePath::root()).unwrap(), vec!["/test/module"]);
+    assert!(file.object_exists("/test/./module").unwrap());
+}
+
+#[test]
+fn file_lists_what_is_beneath_paths_literally() {
+    let mut file = Image::create_in_memory().unwrap();
+
+    file.import_object("/test_/module", Object::new_module(&WASM).unwrap()).unwrap();
+    file.import_object("/testX/module", Object::new_module(&WASM).unwrap()).unwrap();
+    file.import_object("/test_a/module", Object::new_module(&WASM).unwrap()).unwrap();
+    file.import_object("/100%/module", Object::new_module(&WASM).unwrap()).unwrap();
+
+    let list = |path: &str| file.list_objects(&NamespacePath::parse(path).unwrap()).unwrap();
+    assert_eq!(list("/test_"), vec!["/test_/module"]);
+    assert_eq!(list("/test_/module"), vec!["/test_/module"]);
+    assert_eq!(list("/1%"), Vec::<String>::new());
+    assert_eq!(list("/100%"), vec!["/100%/module"]);
+}
+
+#[test]
+fn file_lists_only_instances_as_instances() {
+    let mut file = Image::create_in_memory().unwrap();
+    let instance = match Object::new_module(&WASM).unwrap() {
+        Object::Module(module) => Object::Instance(module.into()),
+        _ => panic!("expected a module"),
+    };
+
+    file.import_object("/test/module", Object::new_module(&WASM).unwrap()).unwrap();
+    file.import_object("/test/instance", instance).unwrap();
+
+    assert_eq!(
+        file.list_instances().unwrap(),
+        vec![NamespacePath::parse("/test/instance").unwrap()]
+    );
+}
+
+#[test]
+fn migrating_repairs_existing_paths() {
+    let mut file = Image::create_in_memory().unwrap();
+    for path in ["/clash", "/one", "/two", "/three"] {
+        file.import_object(path, Object::new_module(&WASM).unwrap()).unwrap();
+    }
+    file.file.execute_batch(r#"
+        UPDATE namespace SET path = 'prototype' WHERE path = '/one';
+        UPDATE namespace SET path = '..' WHERE path = '/two';
+        UPDATE namespace SET path = 'clash/' WHERE path = '/three';
+    "#).unwrap();
+
+    Image::normalize_namespace_paths(&mut file).unwrap();
+
+    let mut paths = file.list_objects(&NamespacePath::root()).unwrap();
+    paths.sort();
+    assert_eq!(paths, vec!["/clash", "/othismo/lost+found/3", "/othismo/lost+found/4", "/prototype"]);
+}
+
+#[test]
+fn file_keeps_access_rules() {
+    let mut file = Image::create_in_memory().unwrap();
+
+    file.grant("/locked/", "/friend", Permission::Send).unwrap();
+    file.grant("/locked", "*", Permission::Send).unwrap();
+    file.grant("/locked", "/friend", Permission::Send).unwrap();
+    file.revoke("/locked", "*", Permission::Send).unwrap();
+
+    let rules: Vec<(String, String, Permission)> = file
+        .access_rules()
+        .unwrap()
+        .into_iter()
+        .map(|rule| (rule.path.to_string(), rule.principal, rule.permission))
+        .collect();
+
+    assert_eq!(rules, vec![("/locked".to_string(), "/friend".to_string(), Permission::Send)]);
+    assert!(matches!(
+        file.grant("/locked", "friend", Permission::Send),
+        Err(Errors::Othismo(OthismoError::InvalidNamespacePath { .. }))
+    ));
+}
+
+#[test]
+fn file_keeps_capabilities_until_their_processes_are_removed() {
+    let mut file = Image::create_in_memory().unwrap();
+    file.import_object("/holder", Object::new_module(&WASM).unwrap()).unwrap();
+    file.import_object("/service", Object::new_module(&WASM).unwrap()).unwrap();
+    file.set_sandboxed("/holder", true).unwrap();
+
+    let capability = file.mint_capability("/service", "/holder").unwrap();
+
+    let holder = NamespacePath::parse("/holder").unwrap();
+    let capabilities = file.capabilities().unwrap();
+    assert_eq!(capabilities.target(&capability.token, Some(&holder)), Some(&capability.target));
+    assert!(capabilities.is_sandboxed(Some(&holder)));
+    assert!(!file.capability_grants().unwrap()[0].delivered);
+
+    file.remove_object("/service").unwrap();
+    let capabilities = file.capabilities().unwrap();
+    assert_eq!(capabilities.target(&capability.token, Some(&holder)), None);
+    assert!(capabilities.is_sandboxed(Some(&holder)));
+
+    file.remove_object("/holder").unwrap();
+    assert!(!file.capabilities().unwrap().is_sandboxed(Some(&holder)));
+}
+
+#[test]
+fn file_keeps_how_instances_are_supervised() {
+    let mut file = Image::create_in_memory().unwrap();
+    file.import_object("/worker", Object::new_module(&WASM).unwrap()).unwrap();
+    assert_eq!(file.supervision("/worker").unwrap(), Supervision::default());
+
+    let supervision = Supervision {
+        policy: RestartPolicy::Transient,
+        max_restarts: 10,
+        within: Duration::from_secs(60),
+    };
+    file.set_supervision("/worker", &supervision).unwrap();
+    assert_eq!(file.supervision("/worker").unwrap(), supervision);
+    let worker = NamespacePath::parse("/worker").unwrap();
+    assert_eq!(file.supervisions().unwrap().get(&worker), Some(&supervision));
+
+    file.remove_object("/worker").unwrap();
+    assert_eq!(file.supervision("/worker").unwrap(), Supervision::default());
+    assert!(file.supervisions().unwrap().is_empty());
+}
+
+#[test]
+fn file_keeps_dead_letters_until_they_are_purged() {
+    let mut file = Image::create_in_memory().unwrap();
+    let sender = NamespacePath::parse("/sender").unwrap();
+    let first = file
+        .record_dead_letter(Some("/nowhere"), Some(&sender), "no_such_path", "nothing there", b"first")
+        .unwrap();
+    file.record_dead_letter(None, None, "unknown_capability", "no such capability", b"second")
+        .unwrap();
+
+    let dead_letter = file.dead_letter(first).unwrap();
+    assert_eq!(dead_letter.destination.as_deref(), Some("/nowhere"));
+    assert_eq!(dead_letter.sender.as_deref(), Some("/sender"));
+    assert_eq!(dead_letter.code, "no_such_path");
+    assert_eq!(dead_letter.message, b"first");
+    assert_eq!(file.dead_letters().unwrap().len(), 2);
+
+    file.remove_dead_letter(first).unwrap();
+    assert!(matches!(
+        file.dead_letter(first),
+        Err(Errors::Othismo(OthismoError::DeadLetterDoesNotExist))
+    ));
+    assert_eq!(file.purge_dead_letters().unwrap(), 1);
+    assert!(file.dead_letters().unwrap().is_empty());
+}
+
+#[test]
+fn file_keeps_durable_mail_until_it_is_acknowledged() {
+    let mut file = Image::create_in_memory().unwrap();
+    file.import_object("/durable", Object::new_module(&WASM).unwrap()).unwrap();
+    file.set_durable_mailbox("/durable", true).unwrap();
+    let durable = NamespacePath::parse("/durable").unwrap();
+    let sender = NamespacePath::parse("/sender").unwrap();
+    assert!(file.durable_mailboxes().unwrap().contains(&durable));
+
+    let first = file.enqueue_mail(&durable, Some(&sender), b"first").unwrap();
+    file.enqueue_mail(&durable, None, b"second").unwrap();
+    let pending = file.pending_mail(&durable).unwrap();
+    assert_eq!(pending.len(), 2);
+    assert_eq!(pending[0].key, first);
+    assert_eq!(pending[0].sender, Some(sender));
+    assert_eq!(pending[1].message, b"second");
+
+    file.acknowledge_mail(first).unwrap();
+    assert_eq!(file.pending_mail(&durable).unwrap().len(), 1);
+
+    file.remove_object("/durable").unwrap();
+    assert!(file.pending_mail(&durable).unwrap().is_empty());
+    assert!(file.durable_mailboxes().unwrap().is_empty());
+}
+
+#[test]
+fn file_keeps_mailbox_limits_until_they_are_lifted() {
+    let mut file = Image::create_in_memory().unwrap();
+    file.import_object("/busy", Object::new_module(&WASM).unwrap()).unwrap();
+    let busy = NamespacePath::parse("/busy").unwrap();
+    let limit = MailboxLimit { capacity: 10, overflow: Overflow::Reject };
+
+    file.set_mailbox_limit("/busy", Some(limit)).unwrap();
+    assert_eq!(file.mailbox_limits().unwrap().get(&busy), Some(&limit));
+
+    let limit = MailboxLimit { capacity: 5, overflow: Overflow::DropOldest };
+    file.set_mailbox_limit("/busy", Some(limit)).unwrap();
+    assert_eq!(file.mailbox_limits().unwrap().get(&busy), Some(&limit));
+
+    file.set_mailbox_limit("/busy", None).unwrap();
+    assert!(file.mailbox_limits().unwrap().is_empty());
+
+    file.set_mailbox_limit("/busy", Some(limit)).unwrap();
+    file.remove_object("/busy").unwrap();
+    assert!(file.mailbox_limits().unwrap().is_empty());
+}
+
+#[test]
+fn file_keeps_fuel_budgets_until_they_are_lifted() {
+    let mut file = Image::create_in_memory().unwrap();
+    file.import_object("/spinner", Object::new_module(&WASM).unwrap()).unwrap();
+    let spinner = NamespacePath::parse("/spinner").unwrap();
+
+    file.set_fuel_budget("/spinner", Some(1_000)).unwrap();
+    assert_eq!(file.fuel_budgets().unwrap().get(&spinner), Some(&1_000));
+
+    file.set_fuel_budget("/spinner", Some(500)).unwrap();
+    assert_eq!(file.fuel_budgets().unwrap().get(&spinner), Some(&500));
+
+    file.set_fuel_budget("/spinner", None).unwrap();
+    assert!(file.fuel_budgets().unwrap().is_empty());
+
+    file.set_fuel_budget("/spinner", Some(500)).unwrap();
+    file.remove_object("/spinner").unwrap();
+    assert!(file.fuel_budgets().unwrap().is_empty());
+}
+
+#[test]
+fn file_keeps_execution_deadlines_until_they_are_lifted() {
+    let mut file = Image::create_in_memory().unwrap();
+    file.import_object("/slow", Object::new_module(&WASM).unwrap()).unwrap();
+    let slow = NamespacePath::parse("/slow").unwrap();
+
+    file.set_execution_deadline("/slow", Some(Duration::from_millis(250))).unwrap();
+    assert_eq!(
+        file.execution_deadlines().unwrap().get(&slow),
+        Some(&Duration::from_millis(250))
+    );
+
+    file.set_execution_deadline("/slow", None).unwrap();
+    assert!(file.execution_deadlines().unwrap().is_empty());
+
+    file.set_execution_deadline("/slow", Some(Duration::from_secs(1))).unwrap();
+    file.remove_object("/slow").unwrap();
+    assert!(file.execution_deadlines().unwrap().is_empty());
+}
+
+#[test]
+fn file_keeps_memory_limits_until_they_are_lifted() {
+    let mut file = Image::create_in_memory().unwrap();
+    file.import_object("/hungry", Object::new_module(&WASM).unwrap()).unwrap();
+    let hungry = NamespacePath::parse("/hungry").unwrap();
+
+    file.set_memory_limit("/hungry", Some(16)).unwrap();
+    assert_eq!(file.memory_limits().unwrap().get(&hungry), Some(&16));
+
+    file.set_memory_limit("/hungry", None).unwrap();
+    assert!(file.memory_limits().unwrap().is_empty());
+
+    file.set_memory_limit("/hungry", Some(16)).unwrap();
+    file.remove_object("/hungry").unwrap();
+    assert!(file.memory_limits().unwrap().is_empty());
+}
+
+#[test]
+fn file_remembers_what_exactly_once_processes_have_handled() {
+    let mut file = Image::create_in_memory().unwrap();
+    file.import_object("/billing", Object::new_module(&WASM).unwrap()).unwrap();
+    let billing = NamespacePath::parse("/billing").unwrap();
+
+    file.set_exactly_once("/billing", true).unwrap();
+    assert!(file.exactly_once_processes().unwrap().contains(&billing));
+    assert!(file.durable_mailboxes().unwrap().contains(&billing));
+
+    assert!(!file.has_processed(&billing, "first").unwrap());
+    file.mark_processed(&billing, "first").unwrap();
+    assert!(file.has_processed(&billing, "first").unwrap());
+
+    let held = file.hold_outgoing_mail(&billing, "sent", b"outgoing").unwrap();
+    let outgoing = file.outgoing_mail().unwrap();
+    assert_eq!(outgoing.len(), 1);
+    assert_eq!(outgoing[0].sender, billing);
+    assert_eq!(outgoing[0].message_id, "sent");
+    file.release_outgoing_mail(held).unwrap();
+    assert!(file.outgoing_mail().unwrap().is_empty());
+
+    file.remove_object("/billing").unwrap();
+    assert!(!file.has_processed(&billing, "first").unwrap());
+    assert!(file.exactly_once_processes().unwrap().is_empty());
+}
+
+#[test]
+fn file_keeps_hibernation_periods_until_they_are_lifted() {
+    let mut file = Image::create_in_memory().unwrap();
+    file.import_object("/sleepy", Object::new_module(&WASM).unwrap()).unwrap();
+    let sleepy = NamespacePath::parse("/sleepy").unwrap();
+
+    file.set_hibernation_period("/sleepy", Some(Duration::from_secs(60))).unwrap();
+    assert_eq!(
+        file.hibernation_periods().unwrap().get(&sleepy),
+        Some(&Duration::from_secs(60))
+    );
+
+    file.set_hibernation_period("/sleepy", None).unwrap();
+    assert!(file.hibernation_periods().unwrap().is_empty());
+
+    file.set_hibernation_period("/sleepy", Some(Duration::from_secs(1))).unwrap();
+    file.remove_object("/sleepy").unwrap();
+    assert!(file.hibernation_periods().unwrap().is_empty());
+}
+
+#[test]
+fn locked_images_cannot_be_read_by_anything_else() {
+    let directory = module_directory("locked", &[]);
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("locked.simg");
+    let mut file = Image::create(&path).unwrap();
+    file.lock_exclusively().unwrap();
+
+    let other = rusqlite::Connection::open(&path).unwrap();
+    other.busy_timeout(Duration::ZERO).unwrap();
+    let read = other.query_row("SELECT count(*) FROM object", [], |row| {
+        row.get::<_, i64>(0)
+    });
+    assert!(read.is_err());
+
+    drop(file);
+    assert!(Image::open(&path).is_ok());
+}
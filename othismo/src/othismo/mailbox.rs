@@ -0,0 +1,361 @@
+use super::activity::Activity;
+use super::{Message, OthismoError, Result};
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::poll_fn;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tokio::sync::mpsc::error::TryRecvError;
+
+/// What a full mailbox does with one more message: `Block` parks it and holds back whoever
+/// sent it until there's room, `DropOldest` makes room and `Reject` turns it away. Holding a
+/// process back only stops it taking new mail, so each sender (the host counting as one) gets
+/// at most a mailbox's worth parked, and `Block` turns away the rest.
+///
+/// Processes that `Block` on each other's full mailboxes wait on each other forever; `Reject`
+/// or `DropOldest` are the way out of that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    Block,
+    DropOldest,
+    Reject,
+}
+
+impl Overflow {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Overflow::Block => "BLOCK",
+            Overflow::DropOldest => "DROP_OLDEST",
+            Overflow::Reject => "REJECT",
+        }
+    }
+
+    pub fn parse(overflow: &str) -> Result<Overflow> {
+        match overflow.to_ascii_uppercase().replace('-', "_").as_str() {
+            "BLOCK" => Ok(Overflow::Block),
+            "DROP_OLDEST" => Ok(Overflow::DropOldest),
+            "REJECT" => Ok(Overflow::Reject),
+            _ => Err(OthismoError::UnknownOverflowPolicy(overflow.to_string()))?,
+        }
+    }
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MailboxLimit {
+    pub capacity: usize,
+    pub overflow: Overflow,
+}
+
+/// How full a mailbox is, and what it's had to turn away.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MailboxDepth {
+    pub queued: usize,
+    /// Messages waiting for room, whose senders are held back meanwhile.
+    pub parked: usize,
+    pub capacity: Option<usize>,
+    pub dropped: u64,
+    pub rejected: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PostError {
+    Closed,
+    Full,
+}
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<Message>,
+    parked: VecDeque<(Message, Option<Arc<Shared>>)>,
+    senders: usize,
+    receiver_dropped: bool,
+    waker: Option<Waker>,
+    /// Whether the process is still handling the last message it took.
+    in_hand: bool,
+    /// Parked messages let go of when the mailbox was dropped, but not yet abandoned.
+    let_go: usize,
+    dropped: u64,
+    rejected: u64,
+}
+
+struct Shared {
+    limit: Option<MailboxLimit>,
+    activity: Arc<Activity>,
+    /// How many messages this mailbox's process sent are parked in full mailboxes; while any
+    /// are, it isn't handed anything new.
+    held_back: AtomicUsize,
+    state: Mutex<State>,
+}
+
+impl Shared {
+    fn release(&self) {
+        if self.held_back.fetch_sub(1, Ordering::SeqCst) == 1 {
+            if let Some(waker) = self.state.lock().unwrap().waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+fn same_holder(a: &Option<Arc<Shared>>, b: &Option<Arc<Shared>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// A mailbox whose messages count as `activity` from when they're posted until the process is
+/// done handling them.
+pub fn mailbox(limit: Option<MailboxLimit>, activity: Arc<Activity>) -> (MailboxSender, Mailbox) {
+    let shared = Arc::new(Shared {
+        limit,
+        activity,
+        held_back: AtomicUsize::new(0),
+        state: Mutex::new(State {
+            senders: 1,
+            ..State::default()
+        }),
+    });
+
+    (
+        MailboxSender {
+            shared: shared.clone(),
+        },
+        Mailbox { shared },
+    )
+}
+
+pub struct MailboxSender {
+    shared: Arc<Shared>,
+}
+
+impl MailboxSender {
+    /// Posts a message sent by the process whose mailbox is `sender`, handing back whatever
+    /// was dropped to make room for it.
+    pub fn post(
+        &self,
+        message: Message,
+        sender: Option<&MailboxSender>,
+    ) -> Result<Option<Message>, PostError> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.receiver_dropped {
+            return Err(PostError::Closed);
+        }
+
+        let full = self
+            .shared
+            .limit
+            .filter(|limit| state.queue.len() >= limit.capacity);
+        let mut dropped = None;
+        match full.map(|limit| limit.overflow) {
+            None => state.queue.push_back(message),
+            Some(Overflow::DropOldest) => {
+                dropped = state.queue.pop_front();
+                state.dropped += 1;
+                state.queue.push_back(message);
+            }
+
+            Some(Overflow::Reject) => {
+                state.rejected += 1;
+                return Err(PostError::Full);
+            }
+            Some(Overflow::Block) => {
+                // A process can't be held back by its own mailbox
+                let holder = sender
+                    .filter(|sender| !Arc::ptr_eq(&sender.shared, &self.shared))
+                    .map(|sender| sender.shared.clone());
+                let capacity = full.map_or(0, |limit| limit.capacity);
+                let parked = state
+                    .parked
+                    .iter()
+                    .filter(|(_, parked_by)| same_holder(parked_by, &holder))
+                    .count();
+                if parked >= capacity {
+                    state.rejected += 1;
+                    return Err(PostError::Full);
+                }
+                if let Some(holder) = &holder {
+                    holder.held_back.fetch_add(1, Ordering::SeqCst);
+                }
+                state.parked.push_back((message, holder));
+            }
+        }
+        self.shared.activity.add(1);
+        if dropped.is_some() {
+            self.shared.activity.done(1);
+        }
+
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+
+        Ok(dropped)
+    }
+
+    /// Puts a message back in the mailbox whatever its limit, for mail that's being redelivered.
+    pub fn redeliver(&self, message: Message) -> Result<(), PostError> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.receiver_dropped {
+            return Err(PostError::Closed);
+        }
+
+        state.queue.push_back(message);
+        self.shared.activity.add(1);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+
+        Ok(())
+    }
+
+    /// A handle on whatever's left in the mailbox once its process ends, which unlike a sender
+    /// doesn't keep the mailbox open.
+    pub fn remains(&self) -> Remains {
+        Remains {
+            shared: self.shared.clone(),
+        }
+    }
+
+    pub fn depth(&self) -> MailboxDepth {
+        let state = self.shared.state.lock().unwrap();
+
+        MailboxDepth {
+            queued: state.queue.len(),
+            parked: state.parked.len(),
+            capacity: self.shared.limit.map(|limit| limit.capacity),
+            dropped: state.dropped,
+            rejected: state.rejected,
+        }
+    }
+}
+
+impl Clone for MailboxSender {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+
+        MailboxSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for MailboxSender {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+pub struct Remains {
+    shared: Arc<Shared>,
+}
+
+impl Remains {
+    /// Lets go of whatever the process left in its mailbox, once it's ended.
+    pub fn abandon(self) {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.receiver_dropped {
+            return;
+        }
+
+        let abandoned = state.queue.len() + state.let_go + usize::from(state.in_hand);
+        state.queue.clear();
+        state.let_go = 0;
+        state.in_hand = false;
+        drop(state);
+        self.shared.activity.done(abandoned);
+    }
+}
+
+/// A process's inbox; it ends once nothing can post to it anymore.
+pub struct Mailbox {
+    shared: Arc<Shared>,
+}
+
+impl Mailbox {
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        match self.take(Some(cx.waker())) {
+            Ok(message) => Poll::Ready(Some(message)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+
+    pub async fn recv(&mut self) -> Option<Message> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn try_recv(&mut self) -> Result<Message, TryRecvError> {
+        self.take(None)
+    }
+
+    fn take(&mut self, waker: Option<&Waker>) -> Result<Message, TryRecvError> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(waker) = waker {
+            state.waker = Some(waker.clone());
+        }
+        let held_back = self.shared.held_back.load(Ordering::SeqCst) > 0;
+
+        // Asking for another message means the process is done with the last one
+        if state.in_hand {
+            state.in_hand = false;
+            self.shared.activity.done(1);
+        }
+
+        let message = match state.queue.pop_front() {
+            Some(message) if !held_back => message,
+            Some(message) => {
+                state.queue.push_front(message);
+                Err(TryRecvError::Empty)?
+            }
+            None if state.senders == 0 => Err(TryRecvError::Disconnected)?,
+            None => Err(TryRecvError::Empty)?,
+        };
+        state.in_hand = true;
+
+        // There's room for a parked message now
+        let released = match state.parked.pop_front() {
+            Some((parked, holder)) => {
+                state.queue.push_back(parked);
+                holder
+            }
+            None => None,
+        };
+        drop(state);
+        if let Some(holder) = released {
+            holder.release();
+        }
+
+        Ok(message)
+    }
+}
+
+impl Drop for Mailbox {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receiver_dropped = true;
+        let parked: Vec<_> = state.parked.drain(..).collect();
+        state.let_go = parked.len();
+        drop(state);
+
+        for holder in parked.into_iter().filter_map(|(_, holder)| holder) {
+            holder.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
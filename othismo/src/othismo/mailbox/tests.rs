@@ -0,0 +1,164 @@
+use crate::othismo::activity::Activity;
+use crate::othismo::mailbox::{mailbox, Mailbox, MailboxLimit, MailboxSender, Overflow, PostError};
+use crate::othismo::Message;
+use std::sync::Arc;
+use tokio::sync::mpsc::error::TryRecvError;
+
+fn limited(capacity: usize, overflow: Overflow) -> Option<MailboxLimit> {
+    Some(MailboxLimit { capacity, overflow })
+}
+
+fn mailbox_of(limit: Option<MailboxLimit>) -> (MailboxSender, Mailbox) {
+    mailbox(limit, Arc::default())
+}
+
+fn message(byte: u8) -> Message {
+    Message::new(vec![byte])
+}
+
+#[test]
+fn overflow_policies_parse_whatever_the_case() {
+    assert_eq!(
+        Overflow::parse("drop-oldest").unwrap(),
+        Overflow::DropOldest
+    );
+    assert_eq!(Overflow::parse("Reject").unwrap(), Overflow::Reject);
+    assert!(Overflow::parse("sometimes").is_err());
+}
+
+#[test]
+fn full_mailboxes_that_drop_the_oldest_keep_the_newest() {
+    let (sender, mut inbox) = mailbox_of(limited(2, Overflow::DropOldest));
+
+    for byte in 1..=3 {
+        sender.post(message(byte), None).unwrap();
+    }
+
+    assert_eq!(inbox.try_recv().unwrap().bytes(), &[2]);
+    assert_eq!(inbox.try_recv().unwrap().bytes(), &[3]);
+    assert_eq!(sender.depth().dropped, 1);
+}
+
+#[test]
+fn full_mailboxes_that_reject_turn_messages_away() {
+    let (sender, mut inbox) = mailbox_of(limited(1, Overflow::Reject));
+
+    sender.post(message(1), None).unwrap();
+    assert!(matches!(
+        sender.post(message(2), None),
+        Err(PostError::Full)
+    ));
+
+    let depth = sender.depth();
+    assert_eq!(
+        (depth.queued, depth.capacity, depth.rejected),
+        (1, Some(1), 1)
+    );
+    assert_eq!(inbox.try_recv().unwrap().bytes(), &[1]);
+    assert!(matches!(inbox.try_recv(), Err(TryRecvError::Empty)));
+}
+
+#[test]
+fn full_mailboxes_that_block_hold_back_the_sender_until_there_is_room() {
+    let (busy, mut busy_inbox) = mailbox_of(limited(1, Overflow::Block));
+    let (eager, mut eager_inbox) = mailbox(None, Arc::default());
+
+    busy.post(message(1), Some(&eager)).unwrap();
+    busy.post(message(2), Some(&eager)).unwrap();
+    eager.post(message(3), None).unwrap();
+
+    let depth = busy.depth();
+    assert_eq!((depth.queued, depth.parked), (1, 1));
+    assert!(matches!(eager_inbox.try_recv(), Err(TryRecvError::Empty)));
+
+    assert_eq!(busy_inbox.try_recv().unwrap().bytes(), &[1]);
+    assert_eq!(busy.depth().queued, 1);
+    assert_eq!(eager_inbox.try_recv().unwrap().bytes(), &[3]);
+}
+
+#[test]
+fn full_mailboxes_that_block_park_at_most_their_capacity_per_sender() {
+    let (busy, mut busy_inbox) = mailbox_of(limited(2, Overflow::Block));
+    let (eager, _eager_inbox) = mailbox(None, Arc::default());
+
+    for byte in 1..=4 {
+        busy.post(message(byte), Some(&eager)).unwrap();
+    }
+    assert!(matches!(
+        busy.post(message(5), Some(&eager)),
+        Err(PostError::Full)
+    ));
+    // Nothing holds back the host, so it's capped just the same
+    busy.post(message(6), None).unwrap();
+    busy.post(message(7), None).unwrap();
+    assert!(matches!(busy.post(message(8), None), Err(PostError::Full)));
+
+    let depth = busy.depth();
+    assert_eq!((depth.queued, depth.parked, depth.rejected), (2, 4, 2));
+
+    // Taking one makes room for the first parked message, which was the eager process's
+    assert_eq!(busy_inbox.try_recv().unwrap().bytes(), &[1]);
+    busy.post(message(9), Some(&eager)).unwrap();
+    assert!(matches!(busy.post(message(10), None), Err(PostError::Full)));
+}
+
+#[test]
+fn redelivered_mail_ignores_the_limit() {
+    let (sender, mut inbox) = mailbox_of(limited(1, Overflow::Reject));
+
+    sender.redeliver(message(1)).unwrap();
+    sender.redeliver(message(2)).unwrap();
+
+    assert_eq!(inbox.try_recv().unwrap().bytes(), &[1]);
+    assert_eq!(inbox.try_recv().unwrap().bytes(), &[2]);
+}
+
+#[tokio::test]
+async fn mailboxes_end_once_nothing_can_post_to_them() {
+    let (sender, mut inbox) = mailbox(None, Arc::default());
+    sender.post(message(1), None).unwrap();
+    drop(sender);
+
+    assert!(inbox.recv().await.is_some());
+    assert!(inbox.recv().await.is_none());
+}
+
+#[test]
+fn posting_to_a_mailbox_nobody_reads_fails() {
+    let (sender, inbox) = mailbox(None, Arc::default());
+    drop(inbox);
+
+    assert!(matches!(
+        sender.post(message(1), None),
+        Err(PostError::Closed)
+    ));
+}
+
+#[test]
+fn messages_count_as_activity_until_the_process_asks_for_another() {
+    let activity = Arc::new(Activity::default());
+    let (sender, mut inbox) = mailbox(None, activity.clone());
+
+    sender.post(message(1), None).unwrap();
+    assert_eq!(activity.in_flight(), 1);
+    inbox.try_recv().unwrap();
+    assert_eq!(activity.in_flight(), 1);
+    assert!(inbox.try_recv().is_err());
+    assert!(activity.is_idle());
+}
+
+#[test]
+fn abandoned_mail_no_longer_counts() {
+    let activity = Arc::new(Activity::default());
+    let (sender, mut inbox) = mailbox(None, activity.clone());
+    let remains = sender.remains();
+
+    sender.post(message(1), None).unwrap();
+    sender.post(message(2), None).unwrap();
+    inbox.try_recv().unwrap();
+    drop(inbox);
+    assert_eq!(activity.in_flight(), 2);
+
+    remains.abandon();
+    assert!(activity.is_idle());
+}
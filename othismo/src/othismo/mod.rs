@@ -1,4 +1,8 @@
-use bson::{de, Document};
+use activity::Outbox;
+use bson::{de, doc, Document};
+use image::AbiViolation;
+use mailbox::{Mailbox, MailboxSender};
+use namespace_path::NamespacePath;
 use std::cell::RefCell;
 use std::future::Future;
 use std::pin::Pin;
@@ -6,7 +10,9 @@ use std::result;
 use std::sync::{Arc, MutexGuard};
 use std::sync::{Mutex, TryLockError};
 use std::task::Waker;
+use supervision::{Recipe, Supervision};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
 use wasmbin::io::DecodeError;
 use wasmer::{
@@ -14,17 +20,38 @@ use wasmer::{
     MemoryAccessError, RuntimeError,
 };
 
+pub mod acl;
+pub mod activity;
+pub mod capabilities;
 pub mod executors;
+pub mod fuel;
 pub mod image;
+pub mod mailbox;
 pub mod namespace;
+pub mod namespace_path;
+pub mod supervision;
+pub mod tunables;
 
 #[derive(Debug)]
 pub enum OthismoError {
     ImageAlreadyExists,
+    ImageInUse,
     ObjectAlreadyExists,
     ObjectDoesNotExist,
     ObjectNotFree,
-    UnsupportedModuleDefinition(String),
+    RevisionDoesNotExist,
+    DeadLetterDoesNotExist,
+    InvalidNamespacePath { path: String, reason: &'static str },
+    UnknownPermission(String),
+    UnknownRestartPolicy(String),
+    UnknownOverflowPolicy(String),
+    OutOfFuel,
+    TimedOut,
+    GuestThreadEnded,
+    GlobalTypeMismatch(String),
+    MemoryTooLarge(u64),
+    NoSuchMemory(u32),
+    UnsupportedModuleDefinition(Vec<AbiViolation>),
 }
 
 #[derive(Debug)]
@@ -57,6 +84,7 @@ pub enum Errors {
     WasmBin(WasmBinError),
     BsonSerialize(bson::ser::Error),
     BsonDeserialize(bson::de::Error),
+    Wat(wat::Error),
 }
 
 impl From<rusqlite::Error> for Errors {
@@ -131,13 +159,131 @@ impl From<bson::de::Error> for Errors {
     }
 }
 
+impl From<wat::Error> for Errors {
+    fn from(value: wat::Error) -> Self {
+        Errors::Wat(value)
+    }
+}
+
+impl Errors {
+    /// The stable code this error is reported under in `othismo.error` messages.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Errors::Othismo(error) => match error {
+                OthismoError::ImageAlreadyExists => "image_already_exists",
+                OthismoError::ImageInUse => "image_in_use",
+                OthismoError::ObjectAlreadyExists => "already_exists",
+                OthismoError::ObjectDoesNotExist => NO_SUCH_PATH,
+                OthismoError::ObjectNotFree => "object_in_use",
+                OthismoError::RevisionDoesNotExist => "no_such_revision",
+                OthismoError::DeadLetterDoesNotExist => "no_such_dead_letter",
+                OthismoError::InvalidNamespacePath { .. } => "invalid_path",
+                OthismoError::UnknownPermission(_) => "unknown_permission",
+                OthismoError::UnknownRestartPolicy(_) => "unknown_restart_policy",
+                OthismoError::UnknownOverflowPolicy(_) => "unknown_overflow_policy",
+                OthismoError::OutOfFuel => OUT_OF_FUEL,
+                OthismoError::TimedOut => TIMED_OUT,
+                OthismoError::GuestThreadEnded => "instantiation_failed",
+                OthismoError::GlobalTypeMismatch(_) => "global_type_mismatch",
+                OthismoError::MemoryTooLarge(_) => "memory_too_large",
+                OthismoError::NoSuchMemory(_) => "no_such_memory",
+                OthismoError::UnsupportedModuleDefinition(_) => ABI_MISMATCH,
+            },
+            Errors::Wasmer(WasmerError::RuntimeError(_)) => INSTANCE_TRAPPED,
+            Errors::Wasmer(WasmerError::Memory(_)) => INSTANCE_TRAPPED,
+            Errors::Wasmer(WasmerError::Export(_)) => ABI_MISMATCH,
+            Errors::Wasmer(WasmerError::Compile(_) | WasmerError::Instantiation(_)) => {
+                "instantiation_failed"
+            }
+            Errors::WasmParser(_) | Errors::WasmBin(_) | Errors::Wat(_) => "invalid_module",
+            Errors::BsonSerialize(_) | Errors::BsonDeserialize(_) => INVALID_MESSAGE,
+            Errors::Rusqlite(_) => "image_failure",
+            Errors::Io(_) => "io_failure",
+        }
+    }
+}
+
+pub const NO_SUCH_PATH: &str = "no_such_path";
+pub const INSTANCE_TRAPPED: &str = "instance_trapped";
+pub const ABI_MISMATCH: &str = "abi_mismatch";
+pub const INVALID_MESSAGE: &str = "invalid_message";
+pub const PROCESS_EXITED: &str = "process_exited";
+pub const ACCESS_DENIED: &str = "access_denied";
+pub const UNKNOWN_CAPABILITY: &str = "unknown_capability";
+pub const MAILBOX_FULL: &str = "mailbox_full";
+pub const OUT_OF_FUEL: &str = "out_of_fuel";
+pub const TIMED_OUT: &str = "timed_out";
+
+/// Where replies to `document` go: its `reply_to`, or else whoever the router says sent it.
+pub fn reply_address(document: &Document) -> Option<NamespacePath> {
+    let envelope = document.get_document("othismo").ok()?;
+    let address = envelope
+        .get_str("reply_to")
+        .or_else(|_| envelope.get_str("sent_from"))
+        .ok()?;
+
+    NamespacePath::parse(address).ok()
+}
+
+pub fn is_error(document: &Document) -> bool {
+    document.contains_key("othismo.error")
+}
+
+/// An `othismo.error` for `recipient`, naming the message it answers when that had an id.
+pub fn error_document(
+    recipient: &NamespacePath,
+    in_reply_to: Option<&Document>,
+    code: &str,
+    message: &str,
+) -> Document {
+    let mut envelope = doc! { "send_to": recipient.as_str() };
+    let message_id = in_reply_to
+        .and_then(|document| document.get_document("othismo").ok())
+        .and_then(|envelope| envelope.get_str("message_id").ok());
+    if let Some(message_id) = message_id {
+        envelope.insert("in_reply_to", message_id);
+    }
+
+    doc! {
+        "othismo": envelope,
+        "othismo.error": { "code": code, "message": message },
+    }
+}
+
 pub struct Message {
     bytes: Vec<u8>,
+    sender: Option<NamespacePath>,
+    /// Set when the message waits in a durable mailbox until its recipient acknowledges it.
+    mailbox_key: Option<i64>,
+    /// Set by exactly-once senders, whose messages keep their id however often they're sent.
+    message_id: Option<String>,
+    /// Set when the message is held in the image until the router has it.
+    outgoing_key: Option<i64>,
 }
 
 impl Message {
     pub fn new(bytes: Vec<u8>) -> Self {
-        Message { bytes }
+        Message {
+            bytes,
+            sender: None,
+            mailbox_key: None,
+            message_id: None,
+            outgoing_key: None,
+        }
+    }
+
+    pub fn from_document(document: &Document) -> Self {
+        let mut bytes = Vec::new();
+        document
+            .to_writer(&mut bytes)
+            .expect("Failed to convert BSON to message bytes");
+
+        Message::new(bytes)
+    }
+
+    /// The process that sent this message; `None` when it came from the host, e.g. the CLI.
+    pub fn sender(&self) -> Option<&NamespacePath> {
+        self.sender.as_ref()
     }
 
     pub fn bytes(&self) -> &[u8] {
@@ -170,20 +316,54 @@ pub trait ProcessExecutor: Send + 'static {
     fn start(self, context: ProcessCtx) -> Pin<Box<dyn Future<Output = ()> + Send>>;
 }
 
+/// Where a process leaves its mailbox when it hibernates, to be started on again once there's
+/// mail in it.
+pub type Dormant = Arc<Mutex<Option<Mailbox>>>;
+
+/// Asks a running process to save itself to the image, and hears back once it has.
+pub type CheckpointRequest = oneshot::Sender<Result<()>>;
+/// Where a running process that can be checkpointed leaves a way to ask it to.
+pub type Checkpoints = Arc<Mutex<Option<UnboundedSender<CheckpointRequest>>>>;
+
 pub struct ProcessCtx {
-    inbox: UnboundedReceiver<Message>,
-    outbox: UnboundedSender<Message>,
+    name: NamespacePath,
+    inbox: Mailbox,
+    outbox: Outbox,
     waker_slot: Arc<Mutex<Option<Waker>>>,
+    dormant: Dormant,
+    checkpoints: Checkpoints,
 }
 
 pub struct Process {
-    inbox_tx: UnboundedSender<Message>,
-    handle: JoinHandle<()>,
+    id: u64,
+    inbox_tx: MailboxSender,
+    /// `None` for processes that haven't been started yet.
+    handle: Option<JoinHandle<()>>,
     waker: Option<Waker>,
     waker_slot: Arc<Mutex<Option<Waker>>>,
+    supervised: Option<Supervised>,
+    dormant: Dormant,
+    checkpoints: Checkpoints,
+}
+
+/// How to restart a supervised process, and when.
+#[derive(Clone)]
+pub struct Supervised {
+    supervision: Supervision,
+    recipe: Recipe,
 }
 
 impl ProcessCtx {
+    pub fn name(&self) -> &NamespacePath {
+        &self.name
+    }
+
+    /// Sends a message on behalf of this process, so the router knows who sent it.
+    pub fn send(&self, mut message: Message) {
+        message.sender = Some(self.name.clone());
+        self.outbox.send(message).unwrap();
+    }
+
     pub fn get_waker_slot(&self) -> Arc<Mutex<Option<Waker>>> {
         self.waker_slot.clone()
     }
@@ -1,8 +1,8 @@
-use std::sync::Mutex;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{
     cell::RefCell,
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     future::Future,
     pin::Pin,
     sync::{
@@ -12,31 +12,232 @@ use std::{
     },
 };
 
-use bson::Document;
+use bson::oid::ObjectId;
+use bson::{doc, Bson, Document};
 use dashmap::DashMap;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
 
-use crate::othismo::executors::{ConsoleExecutor, InstanceExecutor};
-use crate::othismo::image::Image;
+use crate::othismo;
+use crate::othismo::acl::{AccessControl, Permission};
+use crate::othismo::activity::{Activity, Outbox};
+use crate::othismo::capabilities::{Capabilities, Capability};
+use crate::othismo::executors::{ConsoleExecutor, DurableMailbox, InstanceExecutor};
+use crate::othismo::fuel::{FuelGauge, FuelUsage};
+use crate::othismo::image::{CapabilityGrant, Image, Object};
+use crate::othismo::mailbox::{
+    mailbox, Mailbox, MailboxDepth, MailboxLimit, MailboxSender, PostError,
+};
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::supervision::{
+    event, Events, Exit, Recipe, RestartHistory, Starter, Supervision, EXITED, GAVE_UP, HIBERNATED,
+    RESTARTED,
+};
+use crate::othismo::{Errors, OthismoError};
+
+use super::{
+    error_document, is_error, Channel, Dormant, Message, Process, ProcessCtx, ProcessExecutor,
+    Supervised, ACCESS_DENIED, INVALID_MESSAGE, MAILBOX_FULL, NO_SUCH_PATH, PROCESS_EXITED,
+    UNKNOWN_CAPABILITY,
+};
+
+/// The envelope field capabilities are handed to & passed on by processes in.
+pub const CAPABILITIES_FIELD: &str = "othismo.capabilities";
+
+static NEXT_PROCESS_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Sent to the supervisor when a process's task returns or panics.
+struct ProcessExit {
+    name: NamespacePath,
+    id: u64,
+    exit: Exit,
+}
+
+/// What it takes to start processes, shared by the namespace & its supervisor.
+#[derive(Clone)]
+struct Spawner {
+    processes: Arc<DashMap<String, Box<Process>>>,
+    dispatch_tx: Outbox,
+    exits_tx: UnboundedSender<ProcessExit>,
+    activity: Arc<Activity>,
+    limits: Arc<RwLock<HashMap<NamespacePath, MailboxLimit>>>,
+}
+
+impl Spawner {
+    fn events(&self) -> Events {
+        Events::new(self.processes.clone(), self.dispatch_tx.clone())
+    }
+
+    /// Starts a process on a fresh mailbox.
+    fn spawn(&self, name: &NamespacePath, start: Starter, supervised: Option<Supervised>) {
+        let limit = self.limits.read().unwrap().get(name).copied();
+        let (inbox_tx, inbox_rx) = mailbox(limit, self.activity.clone());
+        let dormant = Arc::new(Mutex::new(None));
+        self.run(name, start, supervised, inbox_tx, inbox_rx, dormant);
+    }
+
+    /// Registers a supervised process without starting it; its mailbox lies dormant until
+    /// there's mail in it.
+    fn register_dormant(&self, name: &NamespacePath, supervised: Supervised) {
+        let limit = self.limits.read().unwrap().get(name).copied();
+        let (inbox_tx, inbox_rx) = mailbox(limit, self.activity.clone());
+
+        self.processes.insert(
+            name.to_string(),
+            Box::new(Process {
+                id: NEXT_PROCESS_ID.fetch_add(1, Ordering::SeqCst),
+                inbox_tx,
+                handle: None,
+                waker: None,
+                waker_slot: Arc::new(Mutex::new(None)),
+                supervised: Some(supervised),
+                dormant: Arc::new(Mutex::new(Some(inbox_rx))),
+                checkpoints: Arc::new(Mutex::new(None)),
+            }),
+        );
+    }
+
+    /// Starts a dormant process on the mailbox it left behind, provided there's mail in it.
+    /// Its recipe reads the image, so it's followed on the blocking pool rather than whichever
+    /// task found the mail; what comes in meanwhile waits in the mailbox. Until then the process
+    /// is handled by the waking task, so it doesn't look like it's exited.
+    fn wake(&self, name: &NamespacePath) {
+        let Some(process) = self.processes.get(name.as_str()) else {
+            return;
+        };
+        let (inbox_tx, dormant) = (process.inbox_tx.clone(), process.dormant.clone());
+        let Some(supervised) = process.supervised.clone() else {
+            return;
+        };
+        drop(process);
 
-use super::{Channel, Message, Process, ProcessCtx, ProcessExecutor};
+        let mut slot = dormant.lock().unwrap();
+        let depth = inbox_tx.depth();
+        if depth.queued + depth.parked == 0 {
+            return;
+        }
+        let Some(inbox) = slot.take() else {
+            return;
+        };
+        drop(slot);
 
-impl Process {
-    pub fn start<E: ProcessExecutor>(
-        executor: E,
-        ctx: ProcessCtx,
-        inbox_tx: UnboundedSender<Message>,
-    ) -> Self {
-        let waker_slot = ctx.get_waker_slot();
-        let handle = tokio::spawn(executor.start(ctx));
+        let (registered_tx, registered_rx) = oneshot::channel::<()>();
+        let (spawner, waking) = (self.clone(), name.clone());
+        let handle = tokio::task::spawn_blocking(move || {
+            let _ = registered_rx.blocking_recv();
+            match (supervised.recipe)() {
+                Ok(start) => {
+                    spawner.run(&waking, start, Some(supervised), inbox_tx, inbox, dormant)
+                }
+                Err(error) => {
+                    spawner
+                        .events()
+                        .report(event(GAVE_UP, &waking, &format!("{:?}", error)));
+                    drop(inbox);
+                    inbox_tx.remains().abandon();
+                }
+            }
+        });
 
-        Process {
-            inbox_tx,
-            handle,
-            waker: None,
-            waker_slot,
+        if let Some(mut process) = self.processes.get_mut(name.as_str()) {
+            process.handle = Some(handle);
         }
+        let _ = registered_tx.send(());
+    }
+
+    /// Starts a process and a watcher that reports its exit. The task only starts once the
+    /// process is registered, so the supervisor never hears of a process it doesn't know about.
+    fn run(
+        &self,
+        name: &NamespacePath,
+        start: Starter,
+        supervised: Option<Supervised>,
+        inbox_tx: MailboxSender,
+        inbox_rx: Mailbox,
+        dormant: Dormant,
+    ) {
+        let waker_slot = Arc::new(Mutex::new(None));
+        let checkpoints = Arc::new(Mutex::new(None));
+        let ctx = ProcessCtx {
+            name: name.clone(),
+            inbox: inbox_rx,
+            outbox: self.dispatch_tx.clone(),
+            waker_slot: waker_slot.clone(),
+            dormant: dormant.clone(),
+            checkpoints: checkpoints.clone(),
+        };
+
+        let id = NEXT_PROCESS_ID.fetch_add(1, Ordering::SeqCst);
+        let (registered_tx, registered_rx) = oneshot::channel::<()>();
+        let exits_tx = self.exits_tx.clone();
+        let activity = self.activity.clone();
+        let remains = inbox_tx.remains();
+        let watched = name.clone();
+        let hibernated = dormant.clone();
+        let handle = tokio::spawn(async move {
+            let _ = registered_rx.await;
+            let exit = match tokio::spawn(start(ctx)).await {
+                Err(error) if error.is_panic() => Exit::Panicked,
+                _ if hibernated.lock().unwrap().is_some() => Exit::Hibernated,
+                _ => Exit::Finished,
+            };
+            // The exit is work until the supervisor's dealt with it, what was left in the
+            // mailbox isn't anymore
+            activity.add(1);
+            remains.abandon();
+            let reported = exits_tx.send(ProcessExit {
+                name: watched,
+                id,
+                exit,
+            });
+            if reported.is_err() {
+                activity.done(1);
+            }
+        });
+
+        self.processes.insert(
+            name.to_string(),
+            Box::new(Process {
+                id,
+                inbox_tx,
+                handle: Some(handle),
+                waker: None,
+                waker_slot,
+                supervised,
+                dormant,
+                checkpoints,
+            }),
+        );
+        let _ = registered_tx.send(());
+    }
+}
+
+/// Hands a durable process the mail it hadn't acknowledged when it last stopped.
+fn redeliver_mail(
+    image: &Mutex<Image>,
+    processes: &DashMap<String, Box<Process>>,
+    path: &NamespacePath,
+) {
+    let pending = match image.lock().unwrap().pending_mail(path) {
+        Ok(pending) => pending,
+        Err(error) => {
+            println!("couldn't read the mailbox of {}, {:?}", path, error);
+            return;
+        }
+    };
+    let Some(process) = processes.get(path.as_str()) else {
+        return;
+    };
+
+    for mail in pending {
+        let _ = process.inbox_tx.redeliver(Message {
+            bytes: mail.message,
+            sender: mail.sender,
+            mailbox_key: Some(mail.key),
+            message_id: None,
+            outgoing_key: None,
+        });
     }
 }
 
@@ -46,131 +247,709 @@ The Namespace takes care of dispatching messages to the correct recipient.
  */
 
 pub struct Namespace {
+    image: Option<Arc<Mutex<Image>>>,
     processes: Arc<DashMap<String, Box<Process>>>,
-    dispatch_tx: UnboundedSender<Message>,
+    access: Arc<RwLock<AccessControl>>,
+    capabilities: Arc<RwLock<Capabilities>>,
+    durable: Arc<RwLock<HashSet<NamespacePath>>>,
+    dispatch_tx: Outbox,
+    spawner: Spawner,
+    activity: Arc<Activity>,
     messages_sent: Arc<AtomicU64>,
-    last_message_sent_at: Arc<AtomicU64>,
+    fuel: HashMap<NamespacePath, Arc<FuelGauge>>,
 }
 
 struct NamespaceRouter {
+    image: Option<Arc<Mutex<Image>>>,
     processes: Arc<DashMap<String, Box<Process>>>,
+    access: Arc<RwLock<AccessControl>>,
+    capabilities: Arc<RwLock<Capabilities>>,
+    durable: Arc<RwLock<HashSet<NamespacePath>>>,
     dispatch_rx: UnboundedReceiver<Message>,
+    activity: Arc<Activity>,
+    spawner: Spawner,
+}
+
+/// Restarts supervised processes when they exit, and reports what happened to `/othismo/events`.
+struct NamespaceSupervisor {
+    image: Option<Arc<Mutex<Image>>>,
+    spawner: Spawner,
+    durable: Arc<RwLock<HashSet<NamespacePath>>>,
+    exits_rx: UnboundedReceiver<ProcessExit>,
+    activity: Arc<Activity>,
+    history: RestartHistory,
 }
 
 impl Namespace {
     pub fn new() -> Namespace {
+        Namespace::with_image(None)
+    }
+
+    /// With an image, capabilities the namespace mints or passes along are kept in it.
+    fn with_image(image: Option<Arc<Mutex<Image>>>) -> Namespace {
         let (tx, rx) = Channel::new().split();
+        let (exits_tx, exits_rx) = Channel::new().split();
+        let activity = Arc::new(Activity::default());
+        let tx = Outbox::new(tx, activity.clone());
         let processes = Arc::new(DashMap::new());
+        let access = Arc::new(RwLock::new(AccessControl::default()));
+        let capabilities = Arc::new(RwLock::new(Capabilities::default()));
+        let durable = Arc::new(RwLock::new(HashSet::new()));
+
+        let spawner = Spawner {
+            processes: processes.clone(),
+            dispatch_tx: tx.clone(),
+            exits_tx,
+            activity: activity.clone(),
+            limits: Arc::new(RwLock::new(HashMap::new())),
+        };
 
         let mut router = NamespaceRouter {
+            image: image.clone(),
             processes: processes.clone(),
+            access: access.clone(),
+            capabilities: capabilities.clone(),
+            durable: durable.clone(),
             dispatch_rx: rx,
+            activity: activity.clone(),
+            spawner: spawner.clone(),
+        };
+
+        let supervisor = NamespaceSupervisor {
+            image: image.clone(),
+            spawner: spawner.clone(),
+            durable: durable.clone(),
+            exits_rx,
+            activity: activity.clone(),
+            history: RestartHistory::default(),
         };
 
         let mut namespace = Namespace {
+            image,
             processes: processes,
+            access,
+            capabilities,
+            durable,
             dispatch_tx: tx,
+            spawner,
+            activity,
             messages_sent: Arc::new(AtomicU64::new(0)),
-            last_message_sent_at: Arc::new(AtomicU64::new(0)),
+            fuel: HashMap::new(),
         };
 
         tokio::spawn(router.message_loop());
+        tokio::spawn(supervisor.supervise());
 
         namespace
     }
 
-    pub fn create_process<E: ProcessExecutor>(&mut self, executor: E, name: &str) -> () {
-        let (inbox_tx, mut inbox_rx) = Channel::new().split();
-        let outbox_tx = self.dispatch_tx.clone();
+    pub fn set_access_control(&self, access: AccessControl) {
+        *self.access.write().unwrap() = access;
+    }
 
-        let ctx = ProcessCtx {
-            inbox: inbox_rx,
-            outbox: outbox_tx,
-            waker_slot: Arc::new(Mutex::new(Option::None)),
+    pub fn set_capabilities(&self, capabilities: Capabilities) {
+        *self.capabilities.write().unwrap() = capabilities;
+    }
+
+    /// Processes whose mail is kept in the image until they've handled it.
+    pub fn set_durable_mailboxes(&self, durable: HashSet<NamespacePath>) {
+        *self.durable.write().unwrap() = durable;
+    }
+
+    /// How many messages processes may have waiting; it applies as each process is (re)started.
+    pub fn set_mailbox_limits(&self, limits: HashMap<NamespacePath, MailboxLimit>) {
+        *self.spawner.limits.write().unwrap() = limits;
+    }
+
+    pub fn mailbox_depth(&self, process: &NamespacePath) -> Option<MailboxDepth> {
+        self.processes
+            .get(process.as_str())
+            .map(|process| process.inbox_tx.depth())
+    }
+
+    /// The depth of every process's mailbox, by path.
+    pub fn mailbox_depths(&self) -> Vec<(NamespacePath, MailboxDepth)> {
+        let mut depths: Vec<_> = self
+            .processes
+            .iter()
+            .filter_map(|process| {
+                let path = NamespacePath::parse(process.key()).ok()?;
+                Some((path, process.inbox_tx.depth()))
+            })
+            .collect();
+        depths.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+        depths
+    }
+
+    /// Asks every running process kept in the image to save itself there, so it would pick up
+    /// from here were the namespace to stop. Durable ones are already up to date; processes
+    /// that end before they get round to it are left out.
+    pub async fn checkpoint(&self) -> Vec<(NamespacePath, othismo::Result<()>)> {
+        let requested: Vec<_> = self
+            .processes
+            .iter()
+            .filter_map(|process| {
+                let checkpoints = process.checkpoints.lock().unwrap().clone()?;
+                let path = NamespacePath::parse(process.key()).ok()?;
+                let (done, saved) = oneshot::channel();
+                checkpoints.send(done).ok()?;
+                Some((path, saved))
+            })
+            .collect();
+
+        let mut checkpointed = Vec::new();
+        for (path, saved) in requested {
+            if let Ok(saved) = saved.await {
+                checkpointed.push((path, saved));
+            }
+        }
+        checkpointed.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+        checkpointed
+    }
+
+    /// Reports events to `/othismo/events`, when something's running there.
+    pub fn events(&self) -> Events {
+        self.spawner.events()
+    }
+
+    /// How much fuel each metered instance has burnt, by path.
+    pub fn fuel_usage(&self) -> Vec<(NamespacePath, FuelUsage)> {
+        let mut usage: Vec<_> = self
+            .fuel
+            .iter()
+            .map(|(path, gauge)| (path.clone(), gauge.usage()))
+            .collect();
+        usage.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+        usage
+    }
+
+    /// Mints a capability for `target`, gives it to `holder` and tells `holder` about it.
+    pub fn grant_capability(
+        &self,
+        holder: &NamespacePath,
+        target: &NamespacePath,
+    ) -> othismo::Result<Capability> {
+        let capability = Capability {
+            token: Capabilities::mint_token(),
+            target: target.clone(),
         };
 
-        assert!(!self.processes.contains_key(name));
-        let process = Box::new(Process::start(executor, ctx, inbox_tx));
-        self.processes.insert(name.to_string(), process);
+        if let Some(image) = &self.image {
+            image.lock().unwrap().transaction(|image| {
+                image.insert_capability(&capability)?;
+                image.give_capability(&capability.token, holder, true)
+            })?;
+        }
+        let mut capabilities = self.capabilities.write().unwrap();
+        capabilities.insert(capability.clone());
+        capabilities.give(&capability.token, holder);
+        drop(capabilities);
+
+        self.deliver_capability(holder, &capability);
+
+        Ok(capability)
     }
 
-    pub fn send_document(&self, destination: &str, document: Document) {
+    pub fn revoke_capability(&self, token: &str) -> othismo::Result<()> {
+        if let Some(image) = &self.image {
+            image.lock().unwrap().revoke_capability(token)?;
+        }
+        self.capabilities.write().unwrap().revoke(token);
+
+        Ok(())
+    }
+
+    pub fn set_sandboxed(&self, process: &NamespacePath, sandboxed: bool) -> othismo::Result<()> {
+        if let Some(image) = &self.image {
+            image
+                .lock()
+                .unwrap()
+                .set_sandboxed(process.as_str(), sandboxed)?;
+        }
+        self.capabilities
+            .write()
+            .unwrap()
+            .set_sandboxed(process, sandboxed);
+
+        Ok(())
+    }
+
+    /// Sends a dead letter again, to `destination` if given or else where it was first headed,
+    /// and forgets it. Replies go to its original sender unless it named a `reply_to`.
+    pub fn replay_dead_letter(&self, id: i64, destination: Option<&str>) -> othismo::Result<()> {
+        let Some(image) = &self.image else {
+            Err(OthismoError::DeadLetterDoesNotExist)?
+        };
+        let mut image = image.lock().unwrap();
+        let dead_letter = image.dead_letter(id)?;
+
+        let mut document = Document::from_reader(dead_letter.message.as_slice())?;
+        let mut envelope = document
+            .get_document("othismo")
+            .cloned()
+            .unwrap_or_default();
+        if let Some(destination) = destination {
+            envelope.insert("send_to", NamespacePath::parse(destination)?.as_str());
+            envelope.remove("send_via");
+        }
+        if let (Some(sender), false) = (&dead_letter.sender, envelope.contains_key("reply_to")) {
+            envelope.insert("reply_to", sender);
+        }
+        let send_to = NamespacePath::parse(envelope.get_str("send_to").unwrap_or("/"))?;
+        document.insert("othismo", envelope);
+
+        image.remove_dead_letter(id)?;
+        drop(image);
+        self.send_document(&send_to, document);
+
+        Ok(())
+    }
+
+    fn deliver_capability(&self, holder: &NamespacePath, capability: &Capability) {
+        self.send_document(
+            holder,
+            doc! {
+                "othismo": { "send_to": holder.as_str() },
+                CAPABILITIES_FIELD: [capability.to_document()],
+            },
+        );
+    }
+
+    pub fn create_process<E: ProcessExecutor>(&mut self, executor: E, name: &NamespacePath) -> () {
+        assert!(!self.processes.contains_key(name.as_str()));
+        self.spawner
+            .spawn(name, Box::new(move |ctx| executor.start(ctx)), None);
+    }
+
+    /// Starts a process that's restarted as `supervision` says whenever it exits, each time with
+    /// a fresh executor from `make`.
+    pub fn supervise<E, F>(
+        &mut self,
+        name: &NamespacePath,
+        supervision: Supervision,
+        make: F,
+    ) -> othismo::Result<()>
+    where
+        E: ProcessExecutor,
+        F: Fn() -> othismo::Result<E> + Send + Sync + 'static,
+    {
+        let recipe = recipe_of(make);
+        let start = recipe()?;
+
+        assert!(!self.processes.contains_key(name.as_str()));
+        self.spawner.spawn(
+            name,
+            start,
+            Some(Supervised {
+                supervision,
+                recipe,
+            }),
+        );
+
+        Ok(())
+    }
+
+    /// Like `supervise`, but the process is only started once there's mail for it, and again
+    /// whenever mail comes in after it's hibernated.
+    pub fn supervise_on_demand<E, F>(
+        &mut self,
+        name: &NamespacePath,
+        supervision: Supervision,
+        make: F,
+    ) where
+        E: ProcessExecutor,
+        F: Fn() -> othismo::Result<E> + Send + Sync + 'static,
+    {
+        assert!(!self.processes.contains_key(name.as_str()));
+        self.spawner.register_dormant(
+            name,
+            Supervised {
+                supervision,
+                recipe: recipe_of(make),
+            },
+        );
+    }
+
+    pub fn send_document(&self, destination: &NamespacePath, document: Document) {
         let mut buffer = Vec::new();
         document.to_writer(&mut buffer);
         self.send_message(destination, Message::new(buffer));
     }
 
-    pub fn send_message(&self, destination: &str, message: Message) {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+    /// Sends `message` to `destination` from the host, whatever `send_to` it already had; one
+    /// naming a capability in `send_via` still goes through that. Bytes that aren't a document
+    /// are left for the router to turn away.
+    pub fn send_message(&self, destination: &NamespacePath, mut message: Message) {
+        if let Ok(mut document) = Document::from_reader(message.bytes()) {
+            if !matches!(document.get("othismo"), Some(Bson::Document(_))) {
+                document.insert("othismo", Document::new());
+            }
+            if let Ok(envelope) = document.get_document_mut("othismo") {
+                envelope.insert("send_to", destination.as_str());
+            }
+            message.bytes = Message::from_document(&document).bytes;
+        }
         self.messages_sent.fetch_add(1, Ordering::SeqCst);
-        self.last_message_sent_at.store(now, Ordering::SeqCst);
 
         self.dispatch_tx.send(message).unwrap()
     }
 
+    /// Waits, for at most `duration`, until no message is being routed, waiting in a mailbox or
+    /// being handled and the supervisor's dealt with every exit.
     pub async fn wait_for_idleness(&self, duration: Duration) -> () {
-        let idle_timeout = Duration::from_secs(10);
-        let started = SystemTime::now();
+        let _ = tokio::time::timeout(duration, self.activity.idle()).await;
+    }
 
-        loop {
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-            let idleness =
-                Duration::from_secs(now - self.last_message_sent_at.load(Ordering::SeqCst));
+    pub fn is_idle(&self) -> bool {
+        self.activity.is_idle()
+    }
+}
+
+fn recipe_of<E, F>(make: F) -> Recipe
+where
+    E: ProcessExecutor,
+    F: Fn() -> othismo::Result<E> + Send + Sync + 'static,
+{
+    Arc::new(move || {
+        let executor = make()?;
+        Ok(Box::new(move |ctx| executor.start(ctx)) as Starter)
+    })
+}
+
+/// Records who sent a message, overwriting whatever the sender claimed, and gives it an id,
+/// unless an exactly-once sender already did. Messages from the host carry no `sent_from`.
+fn stamp_envelope(
+    document: &mut Document,
+    sender: Option<&NamespacePath>,
+    message_id: Option<&str>,
+) {
+    if !matches!(document.get("othismo"), Some(Bson::Document(_))) {
+        document.insert("othismo", Document::new());
+    }
+    let Ok(envelope) = document.get_document_mut("othismo") else {
+        return;
+    };
+
+    match message_id {
+        Some(message_id) => envelope.insert("message_id", message_id),
+        None => envelope.insert("message_id", ObjectId::new().to_hex()),
+    };
+    match sender {
+        Some(sender) => envelope.insert("sent_from", sender.as_str()),
+        None => envelope.remove("sent_from"),
+    };
+}
+
+/// Where a message was headed: its `send_to`, or the root when it names neither that nor a
+/// capability to send through.
+fn intended_destination(document: &Document) -> Option<String> {
+    let envelope = document.get_document("othismo").ok();
+    match envelope.and_then(|envelope| envelope.get_str("send_to").ok()) {
+        Some(send_to) => Some(send_to.to_string()),
+        None if envelope.is_some_and(|envelope| envelope.contains_key("send_via")) => None,
+        None => Some(NamespacePath::root().to_string()),
+    }
+}
+
+impl NamespaceRouter {
+    /// Works out where a message goes: through a capability it names in `send_via`, or else to
+    /// its `send_to` path, which sandboxed senders can't use. Capabilities listed in
+    /// `othismo.capabilities` are passed on to the recipient, provided the sender holds them.
+    fn route(&self, message: &Message) -> Result<(NamespacePath, Message), (&'static str, String)> {
+        let mut document = Document::from_reader(message.bytes())
+            .map_err(|error| (INVALID_MESSAGE, error.to_string()))?;
+        let sender = message.sender();
+        let capabilities = self.capabilities.read().unwrap();
+        let envelope = document.get_document("othismo").ok();
 
-            if idleness > idle_timeout {
-                return;
+        let via_capability = envelope.and_then(|envelope| envelope.get_str("send_via").ok());
+        let destination = match via_capability {
+            Some(token) => capabilities
+                .target(token, sender)
+                .cloned()
+                .ok_or((UNKNOWN_CAPABILITY, "no such capability is held".to_string()))?,
+            None if capabilities.is_sandboxed(sender) => Err((
+                ACCESS_DENIED,
+                "sandboxed processes can only send through capabilities".to_string(),
+            ))?,
+            None => match envelope.and_then(|envelope| envelope.get_str("send_to").ok()) {
+                Some(send_to) => NamespacePath::parse(send_to)
+                    .map_err(|error| (NO_SUCH_PATH, format!("{:?}", error)))?,
+                None => NamespacePath::root(),
+            },
+        };
+
+        if let Some((permission, target)) =
+            self.denied_permission(sender, &destination, via_capability.is_some())
+        {
+            let reason = format!("{} is not allowed on {}", permission, target);
+            Err((ACCESS_DENIED, reason))?
+        }
+
+        drop(capabilities);
+
+        if document.contains_key(CAPABILITIES_FIELD) {
+            self.pass_on_capabilities(&mut document, sender, &destination)?;
+        }
+        stamp_envelope(&mut document, sender, message.message_id.as_deref());
+
+        Ok((
+            destination,
+            Message {
+                bytes: Message::from_document(&document).bytes,
+                sender: message.sender.clone(),
+                mailbox_key: None,
+                message_id: message.message_id.clone(),
+                outgoing_key: None,
+            },
+        ))
+    }
+
+    fn pass_on_capabilities(
+        &self,
+        document: &mut Document,
+        sender: Option<&NamespacePath>,
+        destination: &NamespacePath,
+    ) -> Result<(), (&'static str, String)> {
+        let capabilities = self.capabilities.read().unwrap();
+        let delegated = document.get_array(CAPABILITIES_FIELD).map_err(|_| {
+            (
+                UNKNOWN_CAPABILITY,
+                "capabilities are passed on as a list".to_string(),
+            )
+        })?;
+
+        let mut passed_on = Vec::new();
+        for entry in delegated {
+            let token = match entry {
+                Bson::Document(entry) => entry.get_str("token").ok(),
+                Bson::String(token) => Some(token.as_str()),
+                _ => None,
+            };
+            let target = token.and_then(|token| capabilities.target(token, sender));
+            match (token, target) {
+                (Some(token), Some(target)) => passed_on.push(Capability {
+                    token: token.to_string(),
+                    target: target.clone(),
+                }),
+                _ => Err((
+                    UNKNOWN_CAPABILITY,
+                    "only held capabilities can be passed on".to_string(),
+                ))?,
             }
+        }
+        drop(capabilities);
 
-            if started.elapsed().unwrap() > duration {
-                return;
+        let mut capabilities = self.capabilities.write().unwrap();
+        for capability in &passed_on {
+            capabilities.give(&capability.token, destination);
+            if let Some(image) = &self.image {
+                let given =
+                    image
+                        .lock()
+                        .unwrap()
+                        .give_capability(&capability.token, destination, true);
+                if let Err(error) = given {
+                    println!("namespace_router ... failed to keep capability {:?}", error);
+                }
             }
+        }
+
+        let passed_on: Vec<Document> = passed_on.iter().map(Capability::to_document).collect();
+        document.insert(CAPABILITIES_FIELD, passed_on);
 
-            tokio::time::sleep(Duration::from_secs(2)).await
+        Ok(())
+    }
+
+    /// Every message needs `SEND` on its destination, unless it was sent through a capability.
+    fn denied_permission(
+        &self,
+        sender: Option<&NamespacePath>,
+        destination: &NamespacePath,
+        via_capability: bool,
+    ) -> Option<(Permission, NamespacePath)> {
+        let access = self.access.read().unwrap();
+        Some((Permission::Send, destination.clone()))
+            .filter(|_| !via_capability)
+            .filter(|(permission, path)| !access.allows(sender, *permission, path))
+    }
+
+    fn deliver(
+        &self,
+        destination: &NamespacePath,
+        message: Message,
+    ) -> Result<(), (&'static str, String)> {
+        // Whoever sent it is held back if it lands in a full mailbox that blocks; taken before
+        // the destination so the two never lock the process table at once.
+        let sender = message
+            .sender()
+            .and_then(|sender| self.processes.get(sender.as_str()))
+            .map(|sender| sender.inbox_tx.clone());
+
+        let Some(process) = self.processes.get(destination.as_str()) else {
+            Err((
+                NO_SUCH_PATH,
+                format!("nothing is running at {}", destination),
+            ))?
+        };
+        let exited = || (PROCESS_EXITED, format!("{} has exited", destination));
+        let dormant = process.dormant.lock().unwrap().is_some();
+        if !dormant && process.handle.as_ref().is_some_and(JoinHandle::is_finished) {
+            Err(exited())?
+        }
+
+        let mut message = message;
+        message.mailbox_key = self.keep_in_mailbox(destination, &message);
+        let mailbox_key = message.mailbox_key;
+        let dropped = match process.inbox_tx.post(message, sender.as_ref()) {
+            Ok(dropped) => dropped,
+            Err(error) => {
+                // It'll be a dead letter instead
+                self.acknowledge(mailbox_key);
+                match error {
+                    PostError::Closed => Err(exited())?,
+                    PostError::Full => Err((
+                        MAILBOX_FULL,
+                        format!("the mailbox of {} is full", destination),
+                    ))?,
+                }
+            }
+        };
+        if let Some(dropped) = dropped {
+            self.acknowledge(dropped.mailbox_key);
+        }
+
+        if let Some(waker) = process.waker_slot.lock().unwrap().as_ref() {
+            waker.wake_by_ref();
+        }
+
+        // Started outside the process table, which starting a process writes to
+        drop(process);
+        self.spawner.wake(destination);
+
+        Ok(())
+    }
+
+    /// Takes mail that won't be handled out of its durable mailbox.
+    fn acknowledge(&self, mailbox_key: Option<i64>) {
+        if let (Some(image), Some(key)) = (&self.image, mailbox_key) {
+            let _ = image.lock().unwrap().acknowledge_mail(key);
+        }
+    }
+
+    /// Writes mail for a durable mailbox to the image before it's handed over, so it survives
+    /// until it's acknowledged.
+    fn keep_in_mailbox(&self, destination: &NamespacePath, message: &Message) -> Option<i64> {
+        let image = self.image.as_ref()?;
+        if !self.durable.read().unwrap().contains(destination) {
+            return None;
+        }
+
+        let kept =
+            image
+                .lock()
+                .unwrap()
+                .enqueue_mail(destination, message.sender(), message.bytes());
+        match kept {
+            Ok(key) => Some(key),
+            Err(error) => {
+                println!("namespace_router ... couldn't keep mail, {:?}", error);
+                None
+            }
+        }
+    }
+
+    /// Lets go of a message an exactly-once sender held in the image, now it's been dealt with.
+    fn release(&self, message: &Message) {
+        if let (Some(image), Some(key)) = (&self.image, message.outgoing_key) {
+            if let Err(error) = image.lock().unwrap().release_outgoing_mail(key) {
+                println!(
+                    "namespace_router ... couldn't release outgoing mail, {:?}",
+                    error
+                );
+            }
+        }
+    }
+
+    /// Keeps an undeliverable message in the image's dead letters, so it can be replayed later.
+    fn bury(&self, message: &Message, destination: Option<&str>, code: &str, reason: &str) {
+        let Some(image) = &self.image else {
+            return;
+        };
+
+        let recorded = image.lock().unwrap().record_dead_letter(
+            destination,
+            message.sender(),
+            code,
+            reason,
+            message.bytes(),
+        );
+        if let Err(error) = recorded {
+            println!(
+                "namespace_router ... couldn't keep a dead letter, {:?}",
+                error
+            );
+        }
+    }
+
+    /// Whether `sender` could send to `destination` by path, as `route` would let it.
+    fn may_send_to(&self, sender: Option<&NamespacePath>, destination: &NamespacePath) -> bool {
+        !self.capabilities.read().unwrap().is_sandboxed(sender)
+            && self.denied_permission(sender, destination, false).is_none()
+    }
+
+    fn reply_with_error(&self, recipient: Option<&NamespacePath>, code: &str, reason: &str) {
+        let Some(recipient) = recipient else {
+            println!("namespace_router ... {}: {}", code, reason);
+            return;
+        };
+
+        let mut reply = error_document(recipient, None, code, reason);
+        stamp_envelope(&mut reply, None, None);
+
+        if let Err((code, reason)) = self.deliver(recipient, Message::from_document(&reply)) {
+            println!(
+                "namespace_router ... undeliverable error, {}: {}",
+                code, reason
+            );
         }
     }
-}
 
-impl NamespaceRouter {
     async fn message_loop(mut self) -> () {
         loop {
             println!("namespace_router ... loop");
             match self.dispatch_rx.recv().await {
                 Some(message) => {
                     println!("namespace_router ... message received");
-                    let document = message.to_bson();
-                    let destination = document
-                        .get_document("othismo")
-                        .and_then(|document| document.get_str("send_to"))
-                        .unwrap_or("unknown");
-
-                    let process = self
-                        .processes
-                        .get(destination)
-                        .or_else(|| self.processes.get("/"))
-                        .expect("No top level process exists");
-
-                    if process.handle.is_finished() {
-                        let (k, v) = self.processes.remove("/").unwrap();
-                        println!("waiting for error...");
-                        v.handle
-                            .await
-                            .inspect_err(|e| println!("This killed the process {}", e));
-                    }
+                    // Only `reply_to` is taken from the message, the sender comes from the host,
+                    // and it's only used if the sender could have sent there itself; errors are
+                    // never answered, so they can't bounce around forever
+                    let document = Document::from_reader(message.bytes()).ok();
+                    let reply_to = match &document {
+                        Some(document) if is_error(document) => None,
+                        Some(document) => document
+                            .get_document("othismo")
+                            .and_then(|envelope| envelope.get_str("reply_to"))
+                            .ok()
+                            .and_then(|reply_to| NamespacePath::parse(reply_to).ok())
+                            .filter(|reply_to| self.may_send_to(message.sender(), reply_to))
+                            .or_else(|| message.sender().cloned()),
+                        None => message.sender().cloned(),
+                    };
 
-                    process
-                        .inbox_tx
-                        .send(message)
-                        .expect("Failed to send message to process");
-
-                    if let Some(waker) = &process.waker {
-                        waker.wake_by_ref();
+                    let delivered = self
+                        .route(&message)
+                        .and_then(|(destination, routed)| self.deliver(&destination, routed));
+                    if let Err((code, reason)) = delivered {
+                        let destination = document.as_ref().and_then(intended_destination);
+                        self.bury(&message, destination.as_deref(), code, &reason);
+                        self.reply_with_error(reply_to.as_ref(), code, &reason);
                     }
+                    self.release(&message);
+                    self.activity.done(1);
                 }
                 None => {
                     println!("namespace_router ... no message received");
@@ -180,26 +959,198 @@ impl NamespaceRouter {
     }
 }
 
-impl From<Image> for Namespace {
-    fn from(image: Image) -> Self {
-        let mut namespace = Namespace::new();
-        let names = image.list_objects("").unwrap();
+impl NamespaceSupervisor {
+    fn report(&self, event: Document) {
+        self.spawner.events().report(event);
+    }
+
+    async fn supervise(mut self) {
+        while let Some(exit) = self.exits_rx.recv().await {
+            self.handle(exit);
+            self.activity.done(1);
+        }
+    }
 
-        namespace.create_process(ConsoleExecutor, "/");
+    fn handle(&mut self, exit: ProcessExit) {
+        // Exits of processes that have since been replaced are old news
+        let current = self
+            .spawner
+            .processes
+            .get(exit.name.as_str())
+            .filter(|process| process.id == exit.id)
+            .map(|process| process.supervised.clone());
+        let Some(supervised) = current else {
+            return;
+        };
+        if exit.exit == Exit::Hibernated {
+            println!("namespace_supervisor ... {} hibernated", exit.name);
+            self.report(event(HIBERNATED, &exit.name, "idle"));
+            // Mail that came in while it was on its way out wakes it straight back up
+            self.spawner.wake(&exit.name);
+            return;
+        }
 
-        for name in image.list_objects("").unwrap() {
-            let object = image.get_object(&name).unwrap();
+        println!(
+            "namespace_supervisor ... {} {}",
+            exit.name,
+            exit.exit.as_str()
+        );
+        self.report(event(EXITED, &exit.name, exit.exit.as_str()));
 
-            match object {
-                super::image::Object::Instance(instance) => {
-                    println!("starting executor for ... {}", &name);
+        let Some(supervised) = supervised else {
+            return;
+        };
+        if !supervised.supervision.restarts_after(exit.exit) {
+            return;
+        }
+        if !self
+            .history
+            .allow(&exit.name, &supervised.supervision, Instant::now())
+        {
+            self.report(event(GAVE_UP, &exit.name, "restarted too often"));
+            return;
+        }
 
-                    let executor: InstanceExecutor = instance.into();
-                    namespace.create_process(executor, &name);
+        match (supervised.recipe)() {
+            Ok(start) => {
+                self.spawner.spawn(&exit.name, start, Some(supervised));
+                if let Some(image) = &self.image {
+                    if self.durable.read().unwrap().contains(&exit.name) {
+                        redeliver_mail(image, &self.spawner.processes, &exit.name);
+                    }
                 }
-                _ => {}
+                self.report(event(RESTARTED, &exit.name, exit.exit.as_str()));
             }
+            Err(error) => self.report(event(GAVE_UP, &exit.name, &format!("{:?}", error))),
         }
-        namespace
     }
 }
+
+impl TryFrom<Image> for Namespace {
+    type Error = Errors;
+
+    fn try_from(image: Image) -> othismo::Result<Self> {
+        let access = image.access_control()?;
+        let capabilities = image.capabilities()?;
+        let durable = image.durable_mailboxes()?;
+        let limits = image.mailbox_limits()?;
+        let exactly_once = image.exactly_once_processes()?;
+        let outgoing = image.outgoing_mail()?;
+        let fuel_budgets = image.fuel_budgets()?;
+        let deadlines = image.execution_deadlines()?;
+        let memory_limits = image.memory_limits()?;
+        let hibernation_periods = image.hibernation_periods()?;
+        let mut supervisions = image.supervisions()?;
+        let undelivered: Vec<CapabilityGrant> = image
+            .capability_grants()?
+            .into_iter()
+            .filter(|grant| !grant.delivered)
+            .collect();
+
+        let instances: Vec<(NamespacePath, Supervision)> = image
+            .list_instances()?
+            .into_iter()
+            .map(|path| {
+                let supervision = supervisions.remove(&path).unwrap_or_default();
+                (path, supervision)
+            })
+            .collect();
+
+        let image = Arc::new(Mutex::new(image));
+        let mut namespace = Namespace::with_image(Some(image.clone()));
+        namespace.set_access_control(access);
+        namespace.set_capabilities(capabilities);
+        namespace.set_durable_mailboxes(durable.clone());
+        namespace.set_mailbox_limits(limits);
+        namespace.create_process(ConsoleExecutor, &NamespacePath::root());
+
+        // Instances are only loaded once there's mail for them
+        for (path, supervision) in instances {
+            println!("registering executor for ... {}", &path);
+
+            // Restarts pick up from whatever was last saved to the image
+            let saved = image.clone();
+            let name = path.clone();
+            let mailbox = durable.contains(&path).then(|| {
+                let mailbox = DurableMailbox::new(image.clone(), path.clone());
+                match exactly_once.contains(&path) {
+                    true => mailbox.exactly_once(),
+                    false => mailbox,
+                }
+            });
+            // One gauge across restarts, so what's burnt adds up
+            let fuel = fuel_budgets
+                .get(&path)
+                .map(|budget| Arc::new(FuelGauge::new(*budget)));
+            if let Some(gauge) = &fuel {
+                namespace.fuel.insert(path.clone(), gauge.clone());
+            }
+            let deadline = deadlines.get(&path).copied();
+            let memory_limit = memory_limits.get(&path).copied();
+            let hibernate_after = hibernation_periods.get(&path).copied();
+            let events = namespace.events();
+            namespace.supervise_on_demand(&path, supervision, move || {
+                let executor = match saved.lock().unwrap().get_object(name.as_str())? {
+                    Object::Instance(instance) => InstanceExecutor::from(instance)
+                        .with_events(events.clone())
+                        .with_image(saved.clone()),
+                    _ => Err(OthismoError::ObjectDoesNotExist)?,
+                };
+
+                let executor = match &mailbox {
+                    Some(mailbox) => executor.with_mailbox(mailbox.clone()),
+                    None => executor,
+                };
+
+                let executor = match &fuel {
+                    Some(fuel) => executor.with_fuel(fuel.clone()),
+                    None => executor,
+                };
+
+                let executor = match deadline {
+                    Some(deadline) => executor.with_deadline(deadline),
+                    None => executor,
+                };
+
+                let executor = match memory_limit {
+                    Some(pages) => executor.with_memory_limit(pages),
+                    None => executor,
+                };
+
+                Ok(match hibernate_after {
+                    Some(after) => executor.with_hibernation(after),
+                    None => executor,
+                })
+            });
+
+            if durable.contains(&path) {
+                redeliver_mail(&image, &namespace.processes, &path);
+                namespace.spawner.wake(&path);
+            }
+        }
+
+        // Sent by exactly-once processes last time, but never routed
+        for mail in outgoing {
+            let _ = namespace.dispatch_tx.send(Message {
+                bytes: mail.message,
+                sender: Some(mail.sender),
+                mailbox_key: None,
+                message_id: Some(mail.message_id),
+                outgoing_key: Some(mail.key),
+            });
+        }
+
+        for grant in undelivered {
+            namespace.deliver_capability(&grant.holder, &grant.capability);
+            image
+                .lock()
+                .unwrap()
+                .give_capability(&grant.capability.token, &grant.holder, true)?;
+        }
+
+        Ok(namespace)
+    }
+}
+
+#[cfg(test)]
+mod tests;
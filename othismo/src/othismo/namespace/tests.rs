@@ -0,0 +1,1034 @@
+use crate::othismo::acl::{AccessControl, AccessRule, Permission};
+use crate::othismo::executors::InstanceExecutor;
+use crate::othismo::fuel::FuelGauge;
+use crate::othismo::image::{Image, InstanceAtRest, Object};
+use crate::othismo::mailbox::{MailboxLimit, Overflow};
+use crate::othismo::namespace::{Namespace, CAPABILITIES_FIELD};
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::supervision::{
+    RestartPolicy, Supervision, EVENTS_PATH, EVENT_FIELD, EXITED, GAVE_UP, HIBERNATED,
+    MEMORY_LIMIT_REACHED, RESTARTED,
+};
+use crate::othismo::{
+    Channel, Message, ProcessCtx, ProcessExecutor, ACCESS_DENIED, INSTANCE_TRAPPED, MAILBOX_FULL,
+    NO_SUCH_PATH, OUT_OF_FUEL, PROCESS_EXITED, TIMED_OUT,
+};
+use bson::{doc, Document};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// Optionally sends one message when started, then reports everything it receives and sends
+/// on whatever it's asked to `relay`.
+struct Probe {
+    send: Option<Document>,
+    received: UnboundedSender<Document>,
+}
+
+impl ProcessExecutor for Probe {
+    fn start(self, mut ctx: ProcessCtx) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(async move {
+            if let Some(document) = self.send {
+                ctx.send(Message::from_document(&document));
+            }
+            while let Some(message) = ctx.inbox.recv().await {
+                let document = message.to_bson();
+                if let Ok(relay) = document.get_document("relay") {
+                    ctx.send(Message::from_document(relay));
+                }
+                let _ = self.received.send(document);
+            }
+        })
+    }
+}
+
+fn probe(
+    namespace: &mut Namespace,
+    name: &str,
+    send: Option<Document>,
+) -> UnboundedReceiver<Document> {
+    let (received, receiver) = Channel::new().split();
+    let path = NamespacePath::parse(name).unwrap();
+    namespace.create_process(Probe { send, received }, &path);
+
+    receiver
+}
+
+async fn next(receiver: &mut UnboundedReceiver<Document>) -> Option<Document> {
+    tokio::time::timeout(Duration::from_millis(500), receiver.recv())
+        .await
+        .ok()
+        .flatten()
+}
+
+#[tokio::test]
+async fn the_router_only_delivers_what_senders_are_granted() {
+    let mut namespace = Namespace::new();
+    namespace.set_access_control(AccessControl::new(vec![AccessRule {
+        path: NamespacePath::parse("/locked").unwrap(),
+        principal: "/friend".to_string(),
+        permission: Permission::Send,
+    }]));
+    probe(&mut namespace, "/", None);
+    let mut locked = probe(&mut namespace, "/locked", None);
+
+    let to_locked = |from: &str| doc! { "othismo": { "send_to": "/locked" }, "from": from };
+    let mut stranger = probe(&mut namespace, "/stranger", Some(to_locked("stranger")));
+    let mut friend = probe(&mut namespace, "/friend", Some(to_locked("friend")));
+
+    let delivered = next(&mut locked).await.unwrap();
+    assert_eq!(delivered.get_str("from").unwrap(), "friend");
+    assert!(next(&mut locked).await.is_none());
+
+    let denial = next(&mut stranger).await.unwrap();
+    let error = denial.get_document("othismo.error").unwrap();
+    assert_eq!(error.get_str("code").unwrap(), "access_denied");
+    assert!(next(&mut friend).await.is_none());
+}
+
+fn relay(namespace: &Namespace, through: &str, document: Document) {
+    namespace.send_document(
+        &NamespacePath::parse(through).unwrap(),
+        doc! { "othismo": { "send_to": through }, "relay": document },
+    );
+}
+
+#[tokio::test]
+async fn sandboxed_processes_only_send_through_capabilities_they_hold() {
+    let mut namespace = Namespace::new();
+    probe(&mut namespace, "/", None);
+    let mut service = probe(&mut namespace, "/service", None);
+    let mut sandboxed = probe(&mut namespace, "/sandboxed", None);
+    let mut friend = probe(&mut namespace, "/friend", None);
+    let sandboxed_path = NamespacePath::parse("/sandboxed").unwrap();
+    namespace.set_sandboxed(&sandboxed_path, true).unwrap();
+
+    relay(
+        &namespace,
+        "/sandboxed",
+        doc! { "othismo": { "send_to": "/service" } },
+    );
+    next(&mut sandboxed).await.unwrap();
+    let denial = next(&mut sandboxed).await.unwrap();
+    assert_eq!(
+        denial
+            .get_document("othismo.error")
+            .unwrap()
+            .get_str("code")
+            .unwrap(),
+        "access_denied"
+    );
+
+    let service_path = NamespacePath::parse("/service").unwrap();
+    let capability = namespace
+        .grant_capability(&sandboxed_path, &service_path)
+        .unwrap();
+    let handed_over = next(&mut sandboxed).await.unwrap();
+    let handed_over = handed_over.get_array(CAPABILITIES_FIELD).unwrap()[0]
+        .as_document()
+        .unwrap()
+        .clone();
+    assert_eq!(handed_over.get_str("token").unwrap(), capability.token);
+    assert_eq!(handed_over.get_str("target").unwrap(), "/service");
+
+    let via = doc! { "othismo": { "send_via": &capability.token }, "hello": "service" };
+    relay(&namespace, "/sandboxed", via.clone());
+    next(&mut sandboxed).await.unwrap();
+    assert_eq!(
+        next(&mut service).await.unwrap().get_str("hello").unwrap(),
+        "service"
+    );
+
+    relay(&namespace, "/friend", via.clone());
+    next(&mut friend).await.unwrap();
+    let denial = next(&mut friend).await.unwrap();
+    assert_eq!(
+        denial
+            .get_document("othismo.error")
+            .unwrap()
+            .get_str("code")
+            .unwrap(),
+        "unknown_capability"
+    );
+    assert!(next(&mut service).await.is_none());
+
+    namespace.set_sandboxed(&sandboxed_path, false).unwrap();
+    relay(
+        &namespace,
+        "/sandboxed",
+        doc! { "othismo": { "send_to": "/friend" }, CAPABILITIES_FIELD: [&capability.token] },
+    );
+    next(&mut friend).await.unwrap();
+    relay(&namespace, "/friend", via.clone());
+    next(&mut friend).await.unwrap();
+    assert_eq!(
+        next(&mut service).await.unwrap().get_str("hello").unwrap(),
+        "service"
+    );
+
+    namespace.revoke_capability(&capability.token).unwrap();
+    relay(&namespace, "/sandboxed", via);
+    assert!(next(&mut service).await.is_none());
+}
+
+#[tokio::test]
+async fn errors_only_go_where_the_sender_could_have_sent_itself() {
+    let mut namespace = Namespace::new();
+    namespace.set_access_control(AccessControl::new(vec![AccessRule {
+        path: NamespacePath::parse("/locked").unwrap(),
+        principal: "/friend".to_string(),
+        permission: Permission::Send,
+    }]));
+    probe(&mut namespace, "/", None);
+    let mut locked = probe(&mut namespace, "/locked", None);
+    let mut stranger = probe(&mut namespace, "/stranger", None);
+    let mut sandboxed = probe(&mut namespace, "/sandboxed", None);
+    let mut inbox = probe(&mut namespace, "/inbox", None);
+    namespace
+        .set_sandboxed(&NamespacePath::parse("/sandboxed").unwrap(), true)
+        .unwrap();
+
+    let nowhere =
+        |reply_to: &str| doc! { "othismo": { "send_to": "/nowhere", "reply_to": reply_to } };
+    relay(&namespace, "/stranger", nowhere("/locked"));
+    next(&mut stranger).await.unwrap();
+    assert_eq!(
+        error_code(&next(&mut stranger).await.unwrap()),
+        NO_SUCH_PATH
+    );
+    assert!(next(&mut locked).await.is_none());
+
+    relay(&namespace, "/sandboxed", nowhere("/inbox"));
+    next(&mut sandboxed).await.unwrap();
+    assert_eq!(
+        error_code(&next(&mut sandboxed).await.unwrap()),
+        ACCESS_DENIED
+    );
+    assert!(next(&mut inbox).await.is_none());
+
+    relay(&namespace, "/stranger", nowhere("/inbox"));
+    next(&mut stranger).await.unwrap();
+    assert_eq!(error_code(&next(&mut inbox).await.unwrap()), NO_SUCH_PATH);
+}
+
+#[tokio::test]
+async fn the_router_stamps_who_sent_each_message() {
+    let mut namespace = Namespace::new();
+    probe(&mut namespace, "/", None);
+    let mut receiver = probe(&mut namespace, "/receiver", None);
+    let forged = doc! { "othismo": { "send_to": "/receiver", "sent_from": "/admin" } };
+    probe(&mut namespace, "/sender", Some(forged.clone()));
+
+    let stamped = next(&mut receiver).await.unwrap();
+    let envelope = stamped.get_document("othismo").unwrap();
+    assert_eq!(envelope.get_str("sent_from").unwrap(), "/sender");
+    let first_id = envelope.get_str("message_id").unwrap().to_string();
+
+    namespace.send_document(&NamespacePath::parse("/receiver").unwrap(), forged);
+    let stamped = next(&mut receiver).await.unwrap();
+    let envelope = stamped.get_document("othismo").unwrap();
+    assert!(envelope.get_str("sent_from").is_err());
+    assert_ne!(envelope.get_str("message_id").unwrap(), first_id);
+}
+
+fn instance(wat: &str) -> InstanceExecutor {
+    let wasm = wasmer::wat2wasm(wat.as_bytes()).unwrap().to_vec();
+    match Object::new_module(&wasm).unwrap() {
+        Object::Module(module) => InstanceExecutor::from(InstanceAtRest::from(module)),
+        _ => panic!("expected a module"),
+    }
+}
+
+fn error_code(document: &Document) -> &str {
+    document
+        .get_document("othismo.error")
+        .unwrap()
+        .get_str("code")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn messages_to_paths_nothing_is_running_at_are_answered_with_errors() {
+    let mut namespace = Namespace::new();
+    probe(&mut namespace, "/", None);
+    let mut sender = probe(
+        &mut namespace,
+        "/sender",
+        Some(doc! { "othismo": { "send_to": "/nowhere" } }),
+    );
+
+    let error = next(&mut sender).await.unwrap();
+    assert_eq!(error_code(&error), NO_SUCH_PATH);
+    assert_eq!(
+        error
+            .get_document("othismo")
+            .unwrap()
+            .get_str("send_to")
+            .unwrap(),
+        "/sender"
+    );
+}
+
+#[tokio::test]
+async fn traps_are_answered_with_errors_at_the_reply_address() {
+    let mut namespace = Namespace::new();
+    probe(&mut namespace, "/", None);
+    let mut inbox = probe(&mut namespace, "/inbox", None);
+    namespace.create_process(
+        instance(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+                (func (export "_message_received") (param i32) unreachable))
+            "#,
+        ),
+        &NamespacePath::parse("/trapping").unwrap(),
+    );
+    let request = doc! { "othismo": { "send_to": "/trapping", "reply_to": "/inbox" } };
+    let mut sender = probe(&mut namespace, "/sender", Some(request.clone()));
+
+    assert_eq!(
+        error_code(&next(&mut inbox).await.unwrap()),
+        INSTANCE_TRAPPED
+    );
+    assert!(next(&mut sender).await.is_none());
+
+    // The instance carries on after a trap
+    relay(&namespace, "/sender", request);
+    assert_eq!(
+        error_code(&next(&mut inbox).await.unwrap()),
+        INSTANCE_TRAPPED
+    );
+}
+
+#[tokio::test]
+async fn instances_that_run_out_of_fuel_are_answered_with_errors() {
+    let mut namespace = Namespace::new();
+    probe(&mut namespace, "/", None);
+    let fuel = Arc::new(FuelGauge::new(10_000));
+    namespace.create_process(
+        instance(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+                (func (export "_message_received") (param i32) (loop $spin (br $spin))))
+            "#,
+        )
+        .with_fuel(fuel.clone()),
+        &NamespacePath::parse("/spinner").unwrap(),
+    );
+    let mut sender = probe(
+        &mut namespace,
+        "/sender",
+        Some(doc! { "othismo": { "send_to": "/spinner" } }),
+    );
+
+    assert_eq!(error_code(&next(&mut sender).await.unwrap()), OUT_OF_FUEL);
+    assert_eq!(fuel.usage().exhausted, 1);
+}
+
+#[tokio::test]
+async fn instances_that_overrun_their_deadline_are_answered_with_errors() {
+    let mut namespace = Namespace::new();
+    probe(&mut namespace, "/", None);
+    namespace.create_process(
+        instance(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+                (func (export "_message_received") (param i32) (loop $spin (br $spin))))
+            "#,
+        )
+        .with_deadline(Duration::from_millis(50)),
+        &NamespacePath::parse("/spinner").unwrap(),
+    );
+    let mut sender = probe(
+        &mut namespace,
+        "/sender",
+        Some(doc! { "othismo": { "send_to": "/spinner" } }),
+    );
+
+    assert_eq!(error_code(&next(&mut sender).await.unwrap()), TIMED_OUT);
+
+    // The router & everyone else carry on meanwhile
+    relay(
+        &namespace,
+        "/sender",
+        doc! { "othismo": { "send_to": "/nowhere" } },
+    );
+    next(&mut sender).await.unwrap();
+    assert_eq!(error_code(&next(&mut sender).await.unwrap()), NO_SUCH_PATH);
+}
+
+#[tokio::test]
+async fn busy_instances_do_not_hold_up_the_rest_of_the_namespace() {
+    let mut namespace = Namespace::new();
+    probe(&mut namespace, "/", None);
+    namespace.create_process(
+        instance(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+                (func (export "_message_received") (param i32) (loop $spin (br $spin))))
+            "#,
+        )
+        .with_fuel(Arc::new(FuelGauge::new(300_000_000))),
+        &NamespacePath::parse("/spinner").unwrap(),
+    );
+    let mut sender = probe(
+        &mut namespace,
+        "/sender",
+        Some(doc! { "othismo": { "send_to": "/spinner" } }),
+    );
+
+    // The test's runtime has one thread, which the spinner would otherwise have to itself
+    relay(
+        &namespace,
+        "/sender",
+        doc! { "othismo": { "send_to": "/nowhere" } },
+    );
+    next(&mut sender).await.unwrap();
+    assert_eq!(error_code(&next(&mut sender).await.unwrap()), NO_SUCH_PATH);
+
+    let spun_out = tokio::time::timeout(Duration::from_secs(30), sender.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(error_code(&spun_out), OUT_OF_FUEL);
+}
+
+#[tokio::test]
+async fn instances_that_cannot_start_answer_with_why() {
+    let mut namespace = Namespace::new();
+    probe(&mut namespace, "/", None);
+    namespace.create_process(
+        instance(
+            r#"(module
+                (memory (export "memory") 4)
+                (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+                (func (export "_message_received") (param i32)))
+            "#,
+        )
+        .with_memory_limit(2),
+        &NamespacePath::parse("/unstartable").unwrap(),
+    );
+    let mut sender = probe(
+        &mut namespace,
+        "/sender",
+        Some(doc! { "othismo": { "send_to": "/unstartable" } }),
+    );
+
+    assert_eq!(
+        error_code(&next(&mut sender).await.unwrap()),
+        "instantiation_failed"
+    );
+}
+
+/// Tells the test each time it starts, and panics on the first message it gets.
+struct Crasher {
+    started: UnboundedSender<()>,
+}
+
+impl ProcessExecutor for Crasher {
+    fn start(self, mut ctx: ProcessCtx) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(async move {
+            let _ = self.started.send(());
+            if ctx.inbox.recv().await.is_some() {
+                panic!("crashing on purpose");
+            }
+        })
+    }
+}
+
+fn crasher(
+    namespace: &mut Namespace,
+    name: &str,
+    supervision: Supervision,
+) -> UnboundedReceiver<()> {
+    let (started, receiver) = Channel::new().split();
+    namespace
+        .supervise(
+            &NamespacePath::parse(name).unwrap(),
+            supervision,
+            move || {
+                Ok(Crasher {
+                    started: started.clone(),
+                })
+            },
+        )
+        .unwrap();
+
+    receiver
+}
+
+fn event_kind(document: &Document) -> &str {
+    document
+        .get_document(EVENT_FIELD)
+        .unwrap()
+        .get_str("kind")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn crashed_processes_are_restarted_until_they_crash_too_often() {
+    let mut namespace = Namespace::new();
+    probe(&mut namespace, "/", None);
+    let mut events = probe(&mut namespace, EVENTS_PATH, None);
+    let mut started = crasher(
+        &mut namespace,
+        "/crasher",
+        Supervision {
+            max_restarts: 1,
+            ..Supervision::default()
+        },
+    );
+    started.recv().await.unwrap();
+
+    namespace.send_document(
+        &NamespacePath::parse("/crasher").unwrap(),
+        doc! { "othismo": { "send_to": "/crasher" } },
+    );
+    assert_eq!(event_kind(&next(&mut events).await.unwrap()), EXITED);
+    assert_eq!(event_kind(&next(&mut events).await.unwrap()), RESTARTED);
+    started.recv().await.unwrap();
+
+    namespace.send_document(
+        &NamespacePath::parse("/crasher").unwrap(),
+        doc! { "othismo": { "send_to": "/crasher" } },
+    );
+    assert_eq!(event_kind(&next(&mut events).await.unwrap()), EXITED);
+    assert_eq!(event_kind(&next(&mut events).await.unwrap()), GAVE_UP);
+}
+
+#[tokio::test]
+async fn temporary_processes_are_left_down() {
+    let mut namespace = Namespace::new();
+    probe(&mut namespace, "/", None);
+    let mut events = probe(&mut namespace, EVENTS_PATH, None);
+    let mut started = crasher(
+        &mut namespace,
+        "/crasher",
+        Supervision {
+            policy: RestartPolicy::Temporary,
+            ..Supervision::default()
+        },
+    );
+    started.recv().await.unwrap();
+
+    namespace.send_document(
+        &NamespacePath::parse("/crasher").unwrap(),
+        doc! { "othismo": { "send_to": "/crasher" } },
+    );
+    let exited = next(&mut events).await.unwrap();
+    assert_eq!(event_kind(&exited), EXITED);
+    assert_eq!(
+        exited
+            .get_document(EVENT_FIELD)
+            .unwrap()
+            .get_str("detail")
+            .unwrap(),
+        "panicked"
+    );
+    assert!(next(&mut events).await.is_none());
+
+    let mut sender = probe(
+        &mut namespace,
+        "/sender",
+        Some(doc! { "othismo": { "send_to": "/crasher" } }),
+    );
+    assert_eq!(
+        error_code(&next(&mut sender).await.unwrap()),
+        PROCESS_EXITED
+    );
+}
+
+#[tokio::test]
+async fn growing_memory_past_its_limit_fails_and_is_reported() {
+    let mut namespace = Namespace::new();
+    probe(&mut namespace, "/", None);
+    let mut events = probe(&mut namespace, EVENTS_PATH, None);
+    let mut inbox = probe(&mut namespace, "/inbox", None);
+    namespace.create_process(
+        instance(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+                (func (export "_message_received") (param i32)
+                    ;; Traps unless growing fails
+                    (if (i32.ne (memory.grow (i32.const 1)) (i32.const -1))
+                        (then unreachable))))
+            "#,
+        )
+        .with_memory_limit(1)
+        .with_events(namespace.events()),
+        &NamespacePath::parse("/hungry").unwrap(),
+    );
+    relay(
+        &namespace,
+        "/inbox",
+        doc! { "othismo": { "send_to": "/hungry", "reply_to": "/inbox" } },
+    );
+    next(&mut inbox).await.unwrap();
+
+    let reported = next(&mut events).await.unwrap();
+    assert_eq!(event_kind(&reported), MEMORY_LIMIT_REACHED);
+    assert!(next(&mut inbox).await.is_none());
+}
+
+#[tokio::test]
+async fn undeliverable_messages_are_kept_and_can_be_replayed_elsewhere() {
+    let mut namespace = Namespace::try_from(Image::create_in_memory().unwrap()).unwrap();
+    let mut receiver = probe(&mut namespace, "/receiver", None);
+    let mut sender = probe(
+        &mut namespace,
+        "/sender",
+        Some(doc! { "othismo": { "send_to": "/nowhere" }, "body": "hello" }),
+    );
+    assert_eq!(error_code(&next(&mut sender).await.unwrap()), NO_SUCH_PATH);
+
+    let dead_letters = namespace
+        .image
+        .as_ref()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .dead_letters()
+        .unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].destination.as_deref(), Some("/nowhere"));
+    assert_eq!(dead_letters[0].sender.as_deref(), Some("/sender"));
+    assert_eq!(dead_letters[0].code, NO_SUCH_PATH);
+
+    namespace
+        .replay_dead_letter(dead_letters[0].id, Some("/receiver"))
+        .unwrap();
+    let replayed = next(&mut receiver).await.unwrap();
+    assert_eq!(replayed.get_str("body").unwrap(), "hello");
+    assert_eq!(
+        replayed
+            .get_document("othismo")
+            .unwrap()
+            .get_str("reply_to")
+            .unwrap(),
+        "/sender"
+    );
+    let image = namespace.image.as_ref().unwrap().lock().unwrap();
+    assert!(image.dead_letters().unwrap().is_empty());
+}
+
+async fn eventually(condition: impl Fn() -> bool) -> bool {
+    for _ in 0..50 {
+        if condition() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    false
+}
+
+#[tokio::test]
+async fn durable_mail_is_redelivered_until_it_is_handled_and_checkpointed() {
+    let path = NamespacePath::parse("/counter").unwrap();
+    let mut image = Image::create_in_memory().unwrap();
+    let module = match Object::new_module_from_wat(
+        r#"(module
+            (memory (export "memory") 1)
+            (global $handled (mut i32) (i32.const 0))
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32)
+                (global.set $handled (i32.add (global.get $handled) (i32.const 1)))))
+        "#,
+    )
+    .unwrap()
+    {
+        Object::Module(module) => module,
+        _ => panic!("expected a module"),
+    };
+    image
+        .import_object(path.as_str(), Object::Instance(module.into()))
+        .unwrap();
+    image.set_durable_mailbox(path.as_str(), true).unwrap();
+    // Left over from the last time the image ran
+    let request = doc! { "othismo": { "send_to": "/counter" } };
+    image
+        .enqueue_mail(&path, None, Message::from_document(&request).bytes())
+        .unwrap();
+
+    let namespace = Namespace::try_from(image).unwrap();
+    let image = namespace.image.clone().unwrap();
+    let settled = |revisions: usize| {
+        let image = image.lock().unwrap();
+        image.pending_mail(&path).unwrap().is_empty()
+            && image.history(path.as_str()).unwrap().len() == revisions
+    };
+    assert!(eventually(|| settled(2)).await);
+
+    namespace.send_document(&path, request);
+    assert!(eventually(|| settled(3)).await);
+}
+
+#[tokio::test]
+async fn exactly_once_instances_drop_duplicates_and_send_once() {
+    let path = NamespacePath::parse("/billing").unwrap();
+    let mut notice = Vec::new();
+    doc! { "othismo": { "send_to": "/inbox" } }
+        .to_writer(&mut notice)
+        .unwrap();
+    let escaped: String = notice
+        .iter()
+        .map(|byte| format!("\\{:02x}", byte))
+        .collect();
+    let module = match Object::new_module_from_wat(&format!(
+        r#"(module
+            (import "othismo" "_send_message" (func $send (param i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "{}")
+            (global $handled (mut i32) (i32.const 0))
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32)
+                (global.set $handled (i32.add (global.get $handled) (i32.const 1)))
+                (drop (call $send (i32.const 0) (i32.const {})))))
+        "#,
+        escaped,
+        notice.len()
+    ))
+    .unwrap()
+    {
+        Object::Module(module) => module,
+        _ => panic!("expected a module"),
+    };
+    let mut image = Image::create_in_memory().unwrap();
+    image
+        .import_object(path.as_str(), Object::Instance(module.into()))
+        .unwrap();
+    image.set_exactly_once(path.as_str(), true).unwrap();
+
+    let mut namespace = Namespace::try_from(image).unwrap();
+    let mut inbox = probe(&mut namespace, "/inbox", None);
+    for _ in 0..2 {
+        let mut charge = Message::from_document(&doc! { "othismo": { "send_to": "/billing" } });
+        charge.message_id = Some("charge-1".to_string());
+        namespace.send_message(&path, charge);
+    }
+
+    let notice = next(&mut inbox).await.unwrap();
+    let notice_id = notice
+        .get_document("othismo")
+        .unwrap()
+        .get_str("message_id")
+        .unwrap();
+    assert_ne!(notice_id, "charge-1");
+    assert!(next(&mut inbox).await.is_none());
+
+    let image = namespace.image.as_ref().unwrap().lock().unwrap();
+    assert_eq!(image.history(path.as_str()).unwrap().len(), 2);
+    assert!(image.has_processed(&path, "charge-1").unwrap());
+    assert!(image.pending_mail(&path).unwrap().is_empty());
+    assert!(image.outgoing_mail().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn the_host_sends_to_the_path_it_names_whatever_the_envelope_says() {
+    let path = NamespacePath::parse("/counter").unwrap();
+    let mut image = Image::create_in_memory().unwrap();
+    let module = match Object::new_module_from_wat(
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32)))
+        "#,
+    )
+    .unwrap()
+    {
+        Object::Module(module) => module,
+        _ => panic!("expected a module"),
+    };
+    image
+        .import_object(path.as_str(), Object::Instance(module.into()))
+        .unwrap();
+    // Only metered instances have their messages counted
+    image
+        .set_fuel_budget(path.as_str(), Some(1_000_000))
+        .unwrap();
+
+    let mut namespace = Namespace::try_from(image).unwrap();
+    let mut elsewhere = probe(&mut namespace, "/elsewhere", None);
+    // As `send-message` does it
+    namespace.send_document(&path, doc! {});
+    namespace.send_document(&path, doc! { "othismo": { "send_to": "/elsewhere" } });
+    namespace.wait_for_idleness(Duration::from_secs(10)).await;
+
+    let usage = namespace.fuel_usage();
+    assert_eq!(usage.len(), 1);
+    assert_eq!(usage[0].0, path);
+    assert_eq!(usage[0].1.messages, 2);
+    assert!(next(&mut elsewhere).await.is_none());
+}
+
+// Two workers, so a router held up by the image can't hold up the test too
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn waking_instances_do_not_hold_up_the_router() {
+    let path = NamespacePath::parse("/sleepy").unwrap();
+    let mut image = Image::create_in_memory().unwrap();
+    let module = match Object::new_module_from_wat(
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32)))
+        "#,
+    )
+    .unwrap()
+    {
+        Object::Module(module) => module,
+        _ => panic!("expected a module"),
+    };
+    image
+        .import_object(path.as_str(), Object::Instance(module.into()))
+        .unwrap();
+    image
+        .set_fuel_budget(path.as_str(), Some(1_000_000))
+        .unwrap();
+
+    let mut namespace = Namespace::try_from(image).unwrap();
+    let mut awake = probe(&mut namespace, "/awake", None);
+    // Waking reads the instance from the image, which is held up here
+    let image = namespace.image.clone().unwrap();
+    let (held_tx, held) = std::sync::mpsc::channel();
+    let (release, released) = std::sync::mpsc::channel::<()>();
+    let holder = std::thread::spawn(move || {
+        let _image = image.lock().unwrap();
+        held_tx.send(()).unwrap();
+        let _ = released.recv();
+    });
+    held.recv().unwrap();
+    namespace.send_document(&path, doc! {});
+    namespace.send_document(&path, doc! {});
+    relay(
+        &namespace,
+        "/awake",
+        doc! { "othismo": { "send_to": "/awake" } },
+    );
+    assert!(next(&mut awake).await.is_some());
+    assert!(next(&mut awake).await.is_some());
+    release.send(()).unwrap();
+    holder.join().unwrap();
+
+    namespace.wait_for_idleness(Duration::from_secs(10)).await;
+    assert_eq!(namespace.fuel_usage()[0].1.messages, 2);
+}
+
+#[tokio::test]
+async fn idle_instances_hibernate_and_wake_up_when_mail_comes_in() {
+    let path = NamespacePath::parse("/sleepy").unwrap();
+    let mut image = Image::create_in_memory().unwrap();
+    let module = match Object::new_module_from_wat(
+        r#"(module
+            (memory (export "memory") 1)
+            (global $handled (mut i32) (i32.const 0))
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32)
+                (global.set $handled (i32.add (global.get $handled) (i32.const 1)))))
+        "#,
+    )
+    .unwrap()
+    {
+        Object::Module(module) => module,
+        _ => panic!("expected a module"),
+    };
+    image
+        .import_object(path.as_str(), Object::Instance(module.into()))
+        .unwrap();
+    image
+        .set_hibernation_period(path.as_str(), Some(Duration::from_millis(100)))
+        .unwrap();
+
+    let mut namespace = Namespace::try_from(image).unwrap();
+    let mut events = probe(&mut namespace, EVENTS_PATH, None);
+    let started = |namespace: &Namespace| {
+        let process = namespace.processes.get(path.as_str()).unwrap();
+        process.handle.is_some()
+    };
+    let revisions = |namespace: &Namespace| {
+        let image = namespace.image.as_ref().unwrap().lock().unwrap();
+        image.history(path.as_str()).unwrap().len()
+    };
+    // Nothing's loaded until there's mail for it
+    assert!(!started(&namespace));
+
+    for saved in [2, 3] {
+        namespace.send_document(&path, doc! { "othismo": { "send_to": "/sleepy" } });
+        let event = tokio::time::timeout(Duration::from_secs(10), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event_kind(&event), HIBERNATED);
+        assert!(started(&namespace));
+        assert_eq!(revisions(&namespace), saved);
+    }
+}
+
+#[tokio::test]
+async fn running_instances_save_themselves_when_checkpointed() {
+    let mut image = Image::create_in_memory().unwrap();
+    let instance: InstanceAtRest = match Object::new_module_from_wat(
+        r#"(module
+            (memory (export "memory") 1)
+            (global $handled (mut i32) (i32.const 0))
+            (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+            (func (export "_message_received") (param i32)
+                (global.set $handled (i32.add (global.get $handled) (i32.const 1)))))
+        "#,
+    )
+    .unwrap()
+    {
+        Object::Module(module) => module.into(),
+        _ => panic!("expected a module"),
+    };
+    for path in ["/busy", "/untouched"] {
+        image
+            .import_object(path, Object::Instance(instance.clone()))
+            .unwrap();
+    }
+
+    let namespace = Namespace::try_from(image).unwrap();
+    namespace.send_document(
+        &NamespacePath::parse("/busy").unwrap(),
+        doc! { "othismo": { "send_to": "/busy" } },
+    );
+    namespace.wait_for_idleness(Duration::from_secs(10)).await;
+
+    // Instances that were never started have nothing new to keep
+    let checkpointed = namespace.checkpoint().await;
+    assert_eq!(checkpointed.len(), 1);
+    assert_eq!(checkpointed[0].0, NamespacePath::parse("/busy").unwrap());
+    assert!(checkpointed[0].1.is_ok());
+
+    let image = namespace.image.as_ref().unwrap().lock().unwrap();
+    assert_eq!(image.history("/busy").unwrap().len(), 2);
+    assert_eq!(image.history("/untouched").unwrap().len(), 1);
+}
+
+/// Never reads its mailbox, so whatever's sent to it piles up.
+struct Stalled;
+
+impl ProcessExecutor for Stalled {
+    fn start(self, ctx: ProcessCtx) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(async move {
+            let _ctx = ctx;
+            std::future::pending::<()>().await
+        })
+    }
+}
+
+#[tokio::test]
+async fn full_mailboxes_that_reject_answer_with_errors() {
+    let stalled = NamespacePath::parse("/stalled").unwrap();
+    let mut namespace = Namespace::new();
+    namespace.set_mailbox_limits(HashMap::from([(
+        stalled.clone(),
+        MailboxLimit {
+            capacity: 1,
+            overflow: Overflow::Reject,
+        },
+    )]));
+    namespace.create_process(Stalled, &stalled);
+    let mut sender = probe(&mut namespace, "/sender", None);
+
+    for _ in 0..2 {
+        relay(
+            &namespace,
+            "/sender",
+            doc! { "othismo": { "send_to": "/stalled" } },
+        );
+    }
+
+    // The sender hears about what it was asked to relay first
+    let mut error = next(&mut sender).await.unwrap();
+    while !error.contains_key("othismo.error") {
+        error = next(&mut sender).await.unwrap();
+    }
+    assert_eq!(error_code(&error), MAILBOX_FULL);
+    let depth = namespace.mailbox_depth(&stalled).unwrap();
+    assert_eq!(
+        (depth.queued, depth.capacity, depth.rejected),
+        (1, Some(1), 1)
+    );
+    assert!(namespace
+        .mailbox_depths()
+        .iter()
+        .any(|(path, depth)| path == &stalled && depth.queued == 1));
+}
+
+#[tokio::test]
+async fn senders_cannot_park_more_than_a_blocking_mailbox_holds() {
+    let stalled = NamespacePath::parse("/stalled").unwrap();
+    let mut namespace = Namespace::new();
+    namespace.set_mailbox_limits(HashMap::from([(
+        stalled.clone(),
+        MailboxLimit {
+            capacity: 1,
+            overflow: Overflow::Block,
+        },
+    )]));
+    namespace.create_process(Stalled, &stalled);
+    let mut inbox = probe(&mut namespace, "/inbox", None);
+
+    let mut flood = Vec::new();
+    doc! { "othismo": { "send_to": "/stalled", "reply_to": "/inbox" } }
+        .to_writer(&mut flood)
+        .unwrap();
+    let escaped: String = flood.iter().map(|byte| format!("\\{:02x}", byte)).collect();
+    // One call sends three messages, before anything can hold it back
+    namespace.create_process(
+        instance(&format!(
+            r#"(module
+                (import "othismo" "_send_message" (func $send (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{}")
+                (func (export "_allocate_message") (param i32) (result i32) i32.const 1024)
+                (func (export "_message_received") (param i32)
+                    (drop (call $send (i32.const 0) (i32.const {length})))
+                    (drop (call $send (i32.const 0) (i32.const {length})))
+                    (drop (call $send (i32.const 0) (i32.const {length})))))
+            "#,
+            escaped,
+            length = flood.len()
+        )),
+        &NamespacePath::parse("/flood").unwrap(),
+    );
+    namespace.send_document(&NamespacePath::parse("/flood").unwrap(), doc! {});
+
+    assert_eq!(error_code(&next(&mut inbox).await.unwrap()), MAILBOX_FULL);
+    assert!(next(&mut inbox).await.is_none());
+    let depth = namespace.mailbox_depth(&stalled).unwrap();
+    assert_eq!((depth.queued, depth.parked, depth.rejected), (1, 1, 1));
+}
+
+#[tokio::test]
+async fn the_namespace_is_idle_as_soon_as_every_message_is_handled() {
+    let mut namespace = Namespace::new();
+    let mut root = probe(&mut namespace, "/", None);
+    let mut sender = probe(&mut namespace, "/sender", None);
+    assert!(namespace.is_idle());
+
+    relay(
+        &namespace,
+        "/sender",
+        doc! { "othismo": { "send_to": "/" } },
+    );
+    assert!(!namespace.is_idle());
+
+    tokio::time::timeout(
+        Duration::from_secs(1),
+        namespace.wait_for_idleness(Duration::from_secs(30)),
+    )
+    .await
+    .unwrap();
+    assert!(namespace.is_idle());
+    assert!(next(&mut sender).await.is_some());
+    assert!(next(&mut root).await.is_some());
+}
@@ -0,0 +1,132 @@
+use super::{OthismoError, Result};
+use std::fmt;
+
+const MAX_PATH_LENGTH: usize = 1024;
+const MAX_SEGMENT_LENGTH: usize = 255;
+
+/// An absolute, normalized location in the namespace, e.g. `/othismo/modules/fizzbuzz`.
+///
+/// Repeated & trailing slashes and `.` segments are normalized away; relative paths, `..`,
+/// empty or over-long segments & control characters are rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NamespacePath(String);
+
+impl NamespacePath {
+    pub fn root() -> NamespacePath {
+        NamespacePath("/".to_string())
+    }
+
+    pub fn parse(path: &str) -> Result<NamespacePath> {
+        let invalid = |reason| {
+            Err(OthismoError::InvalidNamespacePath {
+                path: path.to_string(),
+                reason,
+            })?
+        };
+
+        if !path.starts_with('/') {
+            return invalid("paths must start with `/`");
+        }
+        if path.chars().any(char::is_control) {
+            return invalid("paths can't contain control characters");
+        }
+
+        let mut normalized = String::with_capacity(path.len());
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return invalid("paths can't contain `..`"),
+                _ if segment.len() > MAX_SEGMENT_LENGTH => {
+                    return invalid("path segments can't be longer than 255 bytes")
+                }
+                _ => {
+                    normalized.push('/');
+                    normalized.push_str(segment);
+                }
+            }
+        }
+
+        if normalized.len() > MAX_PATH_LENGTH {
+            return invalid("paths can't be longer than 1024 bytes");
+        }
+        if normalized.is_empty() {
+            return Ok(NamespacePath::root());
+        }
+
+        Ok(NamespacePath(normalized))
+    }
+
+    /// Best-effort repair of a path stored before paths were validated: relative paths are
+    /// rooted at `/` and `..` segments are dropped.
+    pub fn repair(path: &str) -> Result<NamespacePath> {
+        let repaired: String = path
+            .split('/')
+            .filter(|segment| *segment != "..")
+            .map(|segment| {
+                segment
+                    .chars()
+                    .filter(|c| !c.is_control())
+                    .collect::<String>()
+            })
+            .collect::<Vec<String>>()
+            .join("/");
+
+        NamespacePath::parse(&format!("/{}", repaired))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0 == "/"
+    }
+
+    /// Whether `other` is this path or somewhere beneath it.
+    pub fn covers(&self, other: &NamespacePath) -> bool {
+        self.is_root()
+            || other.0 == self.0
+            || (other.0.starts_with(&self.0) && other.0.as_bytes()[self.0.len()] == b'/')
+    }
+
+    pub fn join(&self, segment: &str) -> Result<NamespacePath> {
+        NamespacePath::parse(&format!("{}/{}", self.0, segment))
+    }
+
+    /// Escapes the path for use as the prefix of a `LIKE ... ESCAPE '\'` pattern.
+    pub fn like_prefix(prefix: &str) -> String {
+        let mut escaped = String::with_capacity(prefix.len() + 1);
+        for c in prefix.chars() {
+            if matches!(c, '\\' | '%' | '_') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped.push('%');
+
+        escaped
+    }
+}
+
+impl fmt::Display for NamespacePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for NamespacePath {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<&str> for NamespacePath {
+    type Error = super::Errors;
+
+    fn try_from(path: &str) -> Result<NamespacePath> {
+        NamespacePath::parse(path)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,59 @@
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::{Errors, OthismoError};
+
+fn normalized(path: &str) -> String {
+    NamespacePath::parse(path).unwrap().to_string()
+}
+
+fn rejected(path: &str) -> bool {
+    matches!(
+        NamespacePath::parse(path),
+        Err(Errors::Othismo(OthismoError::InvalidNamespacePath { .. }))
+    )
+}
+
+#[test]
+fn paths_are_normalized() {
+    assert_eq!(normalized("/"), "/");
+    assert_eq!(normalized("//"), "/");
+    assert_eq!(normalized("/a//b/"), "/a/b");
+    assert_eq!(normalized("/a/./b/."), "/a/b");
+    assert_eq!(normalized("/100%_done"), "/100%_done");
+}
+
+#[test]
+fn malformed_paths_are_rejected() {
+    assert!(rejected(""));
+    assert!(rejected("prototype"));
+    assert!(rejected("./relative"));
+    assert!(rejected("/a/../b"));
+    assert!(rejected("/new\nline"));
+    assert!(rejected(&format!("/{}", "a".repeat(256))));
+    assert!(rejected(&"/abcdefgh".repeat(130)));
+}
+
+#[test]
+fn legacy_paths_are_repaired() {
+    assert_eq!(
+        NamespacePath::repair("prototype").unwrap().as_str(),
+        "/prototype"
+    );
+    assert_eq!(NamespacePath::repair("/a/../b/").unwrap().as_str(), "/a/b");
+    assert_eq!(NamespacePath::repair("").unwrap().as_str(), "/");
+}
+
+#[test]
+fn like_prefixes_escape_wildcards() {
+    assert_eq!(NamespacePath::like_prefix("/a_b%c\\"), "/a\\_b\\%c\\\\%");
+}
+
+#[test]
+fn paths_cover_themselves_and_their_descendants() {
+    let path = |p| NamespacePath::parse(p).unwrap();
+
+    assert!(path("/a").covers(&path("/a")));
+    assert!(path("/a").covers(&path("/a/b")));
+    assert!(!path("/a").covers(&path("/ab")));
+    assert!(!path("/a/b").covers(&path("/a")));
+    assert!(NamespacePath::root().covers(&path("/a")));
+}
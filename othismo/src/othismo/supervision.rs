@@ -0,0 +1,173 @@
+use super::activity::Outbox;
+use super::namespace_path::NamespacePath;
+use super::{Message, OthismoError, Process, ProcessCtx, Result};
+use bson::{doc, Document};
+use dashmap::DashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Where the namespace reports processes exiting, restarting & being given up on.
+pub const EVENTS_PATH: &str = "/othismo/events";
+pub const EVENT_FIELD: &str = "othismo.event";
+
+pub const EXITED: &str = "exited";
+pub const RESTARTED: &str = "restarted";
+pub const GAVE_UP: &str = "gave_up";
+pub const MEMORY_LIMIT_REACHED: &str = "memory_limit_reached";
+pub const HIBERNATED: &str = "hibernated";
+
+pub type ProcessFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+pub type Starter = Box<dyn FnOnce(ProcessCtx) -> ProcessFuture + Send>;
+/// Makes a fresh executor for a supervised process, each time it's started.
+pub type Recipe = Arc<dyn Fn() -> Result<Starter> + Send + Sync>;
+
+/// When to restart a process, as in Erlang: `Permanent` ones always, `Transient` ones only
+/// when they crashed and `Temporary` ones never.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    Permanent,
+    Transient,
+    Temporary,
+}
+
+impl RestartPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RestartPolicy::Permanent => "PERMANENT",
+            RestartPolicy::Transient => "TRANSIENT",
+            RestartPolicy::Temporary => "TEMPORARY",
+        }
+    }
+
+    pub fn parse(policy: &str) -> Result<RestartPolicy> {
+        match policy.to_ascii_uppercase().as_str() {
+            "PERMANENT" => Ok(RestartPolicy::Permanent),
+            "TRANSIENT" => Ok(RestartPolicy::Transient),
+            "TEMPORARY" => Ok(RestartPolicy::Temporary),
+            _ => Err(OthismoError::UnknownRestartPolicy(policy.to_string()))?,
+        }
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How a process's task ended. `Hibernated` ones saved themselves & left their mailbox behind,
+/// to be started again once there's mail in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Finished,
+    Panicked,
+    Hibernated,
+}
+
+impl Exit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Exit::Finished => "finished",
+            Exit::Panicked => "panicked",
+            Exit::Hibernated => "hibernated",
+        }
+    }
+}
+
+/// Restarts a process according to `policy`, giving up once it's been restarted
+/// `max_restarts` times `within` a window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Supervision {
+    pub policy: RestartPolicy,
+    pub max_restarts: u32,
+    pub within: Duration,
+}
+
+impl Default for Supervision {
+    fn default() -> Self {
+        Supervision {
+            policy: RestartPolicy::Permanent,
+            max_restarts: 3,
+            within: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Supervision {
+    pub fn restarts_after(&self, exit: Exit) -> bool {
+        if exit == Exit::Hibernated {
+            return false;
+        }
+
+        match self.policy {
+            RestartPolicy::Permanent => true,
+            RestartPolicy::Transient => exit == Exit::Panicked,
+            RestartPolicy::Temporary => false,
+        }
+    }
+}
+
+/// The restarts of each process within its window.
+#[derive(Debug, Default)]
+pub struct RestartHistory {
+    restarts: HashMap<NamespacePath, VecDeque<Instant>>,
+}
+
+impl RestartHistory {
+    /// Records a restart of `process` at `now`, unless it's already used up its restarts.
+    pub fn allow(
+        &mut self,
+        process: &NamespacePath,
+        supervision: &Supervision,
+        now: Instant,
+    ) -> bool {
+        let restarts = self.restarts.entry(process.clone()).or_default();
+        while restarts
+            .front()
+            .is_some_and(|restarted| now.duration_since(*restarted) >= supervision.within)
+        {
+            restarts.pop_front();
+        }
+
+        if restarts.len() >= supervision.max_restarts as usize {
+            return false;
+        }
+
+        restarts.push_back(now);
+        true
+    }
+}
+
+pub fn event(kind: &str, process: &NamespacePath, detail: &str) -> Document {
+    doc! {
+        "othismo": { "send_to": EVENTS_PATH },
+        EVENT_FIELD: { "kind": kind, "path": process.as_str(), "detail": detail },
+    }
+}
+
+/// Reports events to `/othismo/events`, as long as something's running there to hear them.
+#[derive(Clone)]
+pub struct Events {
+    processes: Arc<DashMap<String, Box<Process>>>,
+    outbox: Outbox,
+}
+
+impl Events {
+    pub fn new(processes: Arc<DashMap<String, Box<Process>>>, outbox: Outbox) -> Events {
+        Events { processes, outbox }
+    }
+
+    pub fn report(&self, event: Document) {
+        // Nobody listening isn't worth a dead letter
+        if self.processes.contains_key(EVENTS_PATH) {
+            let _ = self.outbox.send(Message::from_document(&event));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
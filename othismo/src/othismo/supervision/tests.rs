@@ -0,0 +1,47 @@
+use crate::othismo::namespace_path::NamespacePath;
+use crate::othismo::supervision::{Exit, RestartHistory, RestartPolicy, Supervision};
+use std::time::{Duration, Instant};
+
+#[test]
+fn policies_decide_which_exits_are_restarted() {
+    let supervision = |policy| Supervision {
+        policy,
+        ..Supervision::default()
+    };
+
+    assert!(supervision(RestartPolicy::Permanent).restarts_after(Exit::Finished));
+    assert!(supervision(RestartPolicy::Permanent).restarts_after(Exit::Panicked));
+    assert!(!supervision(RestartPolicy::Transient).restarts_after(Exit::Finished));
+    assert!(supervision(RestartPolicy::Transient).restarts_after(Exit::Panicked));
+    assert!(!supervision(RestartPolicy::Temporary).restarts_after(Exit::Panicked));
+    assert!(!supervision(RestartPolicy::Permanent).restarts_after(Exit::Hibernated));
+}
+
+#[test]
+fn policies_parse_whatever_the_case() {
+    assert_eq!(
+        RestartPolicy::parse("transient").unwrap(),
+        RestartPolicy::Transient
+    );
+    assert!(RestartPolicy::parse("sometimes").is_err());
+}
+
+#[test]
+fn restarts_are_limited_within_the_window() {
+    let process = NamespacePath::parse("/crashing").unwrap();
+    let supervision = Supervision {
+        policy: RestartPolicy::Permanent,
+        max_restarts: 2,
+        within: Duration::from_secs(10),
+    };
+    let mut history = RestartHistory::default();
+    let start = Instant::now();
+
+    assert!(history.allow(&process, &supervision, start));
+    assert!(history.allow(&process, &supervision, start + Duration::from_secs(1)));
+    assert!(!history.allow(&process, &supervision, start + Duration::from_secs(2)));
+
+    // Once the first restart falls out of the window there's room for another
+    assert!(history.allow(&process, &supervision, start + Duration::from_secs(10)));
+    assert!(!history.allow(&process, &supervision, start + Duration::from_secs(10)));
+}
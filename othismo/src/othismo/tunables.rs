@@ -0,0 +1,169 @@
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex};
+use wasmer::vm::{
+    LinearMemory, MemoryError, MemoryStyle, TableStyle, VMMemory, VMMemoryDefinition, VMTable,
+    VMTableDefinition,
+};
+use wasmer::{MemoryType, Pages, TableType, Tunables};
+
+/// A growth an instance asked for but didn't get, because it would have gone past its limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefusedGrowth {
+    pub current: Pages,
+    pub requested: Pages,
+}
+
+/// Growths refused since they were last taken, shared by every memory of an instance.
+pub type RefusedGrowths = Arc<Mutex<Vec<RefusedGrowth>>>;
+
+/// Caps each memory an instance has at `limit` pages, whatever maximum its module declares.
+/// Memories that need more than that to start with can't be created at all; growing past it
+/// fails in the guest like any other `memory.grow` would, and is noted in `refused`.
+pub struct LimitingTunables<T: Tunables> {
+    limit: Pages,
+    base: T,
+    refused: RefusedGrowths,
+}
+
+impl<T: Tunables> LimitingTunables<T> {
+    pub fn new(base: T, limit: Pages, refused: RefusedGrowths) -> Self {
+        LimitingTunables {
+            limit,
+            base,
+            refused,
+        }
+    }
+
+    fn adjust_memory(&self, requested: &MemoryType) -> MemoryType {
+        let mut adjusted = *requested;
+        adjusted.maximum = Some(match requested.maximum {
+            Some(maximum) if maximum < self.limit => maximum,
+            _ => self.limit,
+        });
+
+        adjusted
+    }
+
+    fn validate_memory(&self, ty: &MemoryType) -> Result<(), MemoryError> {
+        if ty.minimum > self.limit {
+            return Err(MemoryError::Generic(format!(
+                "needs {} pages to start with, past its limit of {}",
+                ty.minimum.0, self.limit.0
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn limited(&self, memory: VMMemory) -> VMMemory {
+        VMMemory(Box::new(LimitedMemory {
+            memory: memory.0,
+            limit: self.limit,
+            refused: self.refused.clone(),
+        }))
+    }
+}
+
+impl<T: Tunables> Tunables for LimitingTunables<T> {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        self.base.memory_style(&self.adjust_memory(memory))
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(table)
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<VMMemory, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+
+        Ok(self.limited(self.base.create_host_memory(&adjusted, style)?))
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<VMMemory, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        let memory = self
+            .base
+            .create_vm_memory(&adjusted, style, vm_definition_location)?;
+
+        Ok(self.limited(memory))
+    }
+
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<VMTable, String> {
+        self.base.create_host_table(ty, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<VMTable, String> {
+        self.base.create_vm_table(ty, style, vm_definition_location)
+    }
+}
+
+/// A memory that notes each time it's refused a growth past its limit.
+#[derive(Debug)]
+struct LimitedMemory {
+    memory: Box<dyn LinearMemory + 'static>,
+    limit: Pages,
+    refused: RefusedGrowths,
+}
+
+impl LinearMemory for LimitedMemory {
+    fn ty(&self) -> MemoryType {
+        self.memory.ty()
+    }
+
+    fn size(&self) -> Pages {
+        self.memory.size()
+    }
+
+    fn style(&self) -> MemoryStyle {
+        self.memory.style()
+    }
+
+    fn grow(&mut self, delta: Pages) -> Result<Pages, MemoryError> {
+        let current = self.memory.size();
+        let grown = self.memory.grow(delta);
+        if grown.is_err() && current.0.saturating_add(delta.0) > self.limit.0 {
+            self.refused.lock().unwrap().push(RefusedGrowth {
+                current,
+                requested: delta,
+            });
+        }
+
+        grown
+    }
+
+    fn vmmemory(&self) -> NonNull<VMMemoryDefinition> {
+        self.memory.vmmemory()
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn LinearMemory + 'static>, MemoryError> {
+        Ok(Box::new(LimitedMemory {
+            memory: self.memory.try_clone()?,
+            limit: self.limit,
+            refused: self.refused.clone(),
+        }))
+    }
+
+    fn copy(&mut self) -> Result<Box<dyn LinearMemory + 'static>, MemoryError> {
+        Ok(Box::new(LimitedMemory {
+            memory: self.memory.copy()?,
+            limit: self.limit,
+            refused: self.refused.clone(),
+        }))
+    }
+}
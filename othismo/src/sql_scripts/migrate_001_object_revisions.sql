@@ -0,0 +1,19 @@
+alter table object add column revision INTEGER not null default 1;
+alter table object add column revised_at INTEGER not null default 0;
+
+create table object_revision
+(
+    object_key  INTEGER not null,
+    revision    INTEGER not null,
+    kind        TEXT CHECK ( kind IN ('MODULE', 'INSTANCE') ) not null,
+    bytes       BLOB not null,
+    revised_at  INTEGER not null,
+    PRIMARY KEY (object_key, revision),
+    FOREIGN KEY (object_key) REFERENCES object(object_key)
+);
+
+create table setting
+(
+    key     TEXT PRIMARY KEY,
+    value   not null
+);
@@ -0,0 +1,7 @@
+create table access_rule
+(
+    path        TEXT not null,
+    principal   TEXT not null,
+    permission  TEXT CHECK ( permission IN ('SEND', 'INSTANTIATE', 'IMPORT', 'LIST') ) not null,
+    PRIMARY KEY (path, principal, permission)
+);
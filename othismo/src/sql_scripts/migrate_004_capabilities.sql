@@ -0,0 +1,19 @@
+create table capability
+(
+    token   TEXT PRIMARY KEY,
+    target  TEXT not null
+);
+
+create table capability_holder
+(
+    token       TEXT not null,
+    holder      TEXT not null,
+    delivered   INTEGER not null default 0,
+    PRIMARY KEY (token, holder),
+    FOREIGN KEY (token) REFERENCES capability(token)
+);
+
+create table sandboxed_process
+(
+    path    TEXT PRIMARY KEY
+);
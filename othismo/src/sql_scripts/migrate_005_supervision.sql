@@ -0,0 +1,7 @@
+create table supervision
+(
+    path            TEXT PRIMARY KEY,
+    policy          TEXT CHECK ( policy IN ('PERMANENT', 'TRANSIENT', 'TEMPORARY') ) not null,
+    max_restarts    INTEGER not null,
+    within_seconds  INTEGER not null
+);
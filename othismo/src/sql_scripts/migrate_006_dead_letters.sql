@@ -0,0 +1,10 @@
+create table dead_letter
+(
+    dead_letter_key INTEGER PRIMARY KEY,
+    destination     TEXT,
+    sender          TEXT,
+    code            TEXT not null,
+    reason          TEXT not null,
+    message         BLOB not null,
+    received_at     INTEGER not null
+);
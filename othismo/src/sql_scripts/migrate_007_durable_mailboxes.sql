@@ -0,0 +1,15 @@
+create table durable_mailbox
+(
+    path    TEXT PRIMARY KEY
+);
+
+create table mail
+(
+    mail_key    INTEGER PRIMARY KEY,
+    recipient   TEXT not null,
+    sender      TEXT,
+    message     BLOB not null,
+    queued_at   INTEGER not null
+);
+
+create index mail_recipient on mail (recipient, mail_key);
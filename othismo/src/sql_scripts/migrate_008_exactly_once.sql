@@ -0,0 +1,20 @@
+create table exactly_once_process
+(
+    path    TEXT PRIMARY KEY
+);
+
+create table processed_message
+(
+    recipient       TEXT not null,
+    message_id      TEXT not null,
+    processed_at    INTEGER not null,
+    PRIMARY KEY (recipient, message_id)
+);
+
+create table outgoing_mail
+(
+    outgoing_key    INTEGER PRIMARY KEY,
+    sender          TEXT not null,
+    message_id      TEXT not null,
+    message         BLOB not null
+);
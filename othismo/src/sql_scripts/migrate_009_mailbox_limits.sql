@@ -0,0 +1,6 @@
+create table mailbox_limit
+(
+    path        TEXT PRIMARY KEY,
+    capacity    INTEGER not null,
+    overflow    TEXT CHECK ( overflow IN ('BLOCK', 'DROP_OLDEST', 'REJECT') ) not null
+);
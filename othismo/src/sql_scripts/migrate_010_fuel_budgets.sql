@@ -0,0 +1,5 @@
+create table fuel_budget
+(
+    path        TEXT PRIMARY KEY,
+    fuel        INTEGER CHECK ( fuel > 0 ) not null
+);
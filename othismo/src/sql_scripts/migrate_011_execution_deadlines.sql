@@ -0,0 +1,5 @@
+create table execution_deadline
+(
+    path            TEXT PRIMARY KEY,
+    milliseconds    INTEGER CHECK ( milliseconds > 0 ) not null
+);
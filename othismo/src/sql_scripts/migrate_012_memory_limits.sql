@@ -0,0 +1,5 @@
+create table memory_limit
+(
+    path        TEXT PRIMARY KEY,
+    pages       INTEGER CHECK ( pages > 0 ) not null
+);
@@ -0,0 +1,5 @@
+create table hibernation_period
+(
+    path            TEXT PRIMARY KEY,
+    milliseconds    INTEGER CHECK ( milliseconds > 0 ) not null
+);